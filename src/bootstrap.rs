@@ -0,0 +1,85 @@
+use super::endpoints::validate_email;
+use super::password::PasswordHashing;
+use super::{DataBasePool, Role, User};
+
+use uuid::Uuid;
+
+use std::env;
+use std::io::{self, BufRead};
+
+/// creates an administrator whose address counts as verified, the accounts registered over the
+/// api never get a role above `User`
+pub fn create_admin(
+    database: &DataBasePool,
+    passwords: &PasswordHashing,
+    name: &str,
+    email: &str,
+    password: &str,
+) -> Result<Uuid, String> {
+    if password.is_empty() {
+        return Err(String::from("the administrator needs a password"));
+    }
+    validate_email(email).map_err(|e| e.to_string())?;
+
+    let mut database = database.get().map_err(|e| format!("could not connect to the database: {}", e))?;
+    if database.check_user_exists(&name.to_string()).map_err(|e| e.to_string())? {
+        return Err(format!("a user named {} already exists", name));
+    }
+
+    let user = User {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        email: email.to_string(),
+        password: passwords.hash(password).map_err(|e| e.to_string())?,
+        role: Role::Administrator,
+        email_verified: true,
+        totp_enabled: false,
+    };
+    database.create_user(&user).map_err(|e| e.to_string())?;
+
+    Ok(user.id)
+}
+
+/// password for `create-admin`, taken from ADMIN_PASSWORD so it does not end up in the shell
+/// history, otherwise the first line of stdin
+pub fn admin_password() -> Result<String, String> {
+    if let Ok(password) = env::var("ADMIN_PASSWORD") {
+        return Ok(password);
+    }
+
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|e| format!("could not read the password from stdin: {}", e))?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// creates the administrator given in BOOTSTRAP_ADMIN_NAME, BOOTSTRAP_ADMIN_EMAIL and
+/// BOOTSTRAP_ADMIN_PASSWORD on start, for container deployments, nothing happens once any
+/// administrator exists
+pub fn from_env(database: &DataBasePool, passwords: &PasswordHashing) -> Result<(), String> {
+    let name = match env::var("BOOTSTRAP_ADMIN_NAME") {
+        Ok(name) => name,
+        Err(_) => return Ok(()),
+    };
+
+    let has_administrator = database
+        .get()
+        .map_err(|e| format!("could not connect to the database: {}", e))?
+        .has_administrator()
+        .map_err(|e| e.to_string())?;
+    if has_administrator {
+        println!("administrator exists already, ignoring BOOTSTRAP_ADMIN_NAME");
+        return Ok(());
+    }
+
+    let email = env::var("BOOTSTRAP_ADMIN_EMAIL").map_err(|_| String::from("BOOTSTRAP_ADMIN_EMAIL is not set"))?;
+    let password =
+        env::var("BOOTSTRAP_ADMIN_PASSWORD").map_err(|_| String::from("BOOTSTRAP_ADMIN_PASSWORD is not set"))?;
+
+    let id = create_admin(database, passwords, &name, &email, &password)?;
+    println!("created administrator {} ({})", name, id);
+    Ok(())
+}
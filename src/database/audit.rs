@@ -0,0 +1,99 @@
+use super::listing::{Filters, SortField};
+use super::{DataBaseConnection, ListOptions, Page, ServiceError};
+
+use chrono::{DateTime, Utc};
+use postgres::types::Json;
+use postgres::Row;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const AUDIT_COLUMNS: &str = "id, created_at, actor, operation, target_type, target_id, diff, address";
+
+/// one successful mutation, rows are never changed or deleted once written
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    /// missing for operations done without login like `user/register`
+    pub actor: Option<Uuid>,
+    pub operation: String,
+    /// `station`, `region`, `user` or `session`
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    /// changed fields as `{"field": {"before": .., "after": ..}}`
+    pub diff: Option<serde_json::Value>,
+    /// address of the client the operation came from
+    pub address: Option<String>,
+}
+
+impl From<&Row> for AuditEntry {
+    fn from(row: &Row) -> Self {
+        AuditEntry {
+            id: row.get(0),
+            created_at: row.get(1),
+            actor: row.get(2),
+            operation: row.get(3),
+            target_type: row.get(4),
+            target_id: row.get(5),
+            diff: row.get::<usize, Option<Json<serde_json::Value>>>(6).map(|diff| diff.0),
+            address: row.get(7),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<Uuid>,
+    pub operation: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+}
+
+impl DataBaseConnection {
+    pub fn record_audit(&mut self, entry: &AuditEntry) -> Result<(), ServiceError> {
+        self.postgres.execute(
+            "INSERT INTO audit_log (created_at, actor, operation, target_type, target_id, diff, address)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[
+                &entry.created_at,
+                &entry.actor,
+                &entry.operation,
+                &entry.target_type,
+                &entry.target_id,
+                &entry.diff.as_ref().map(Json),
+                &entry.address,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn list_audit(&mut self, filter: &AuditFilter, options: &ListOptions) -> Result<Page<AuditEntry>, ServiceError> {
+        if options.name.is_some() || options.sort == SortField::Name {
+            return Err(ServiceError::Validation(String::from(
+                "audit entries have no name to filter or sort by",
+            )));
+        }
+
+        let mut filters = Filters::new(options);
+
+        if let Some(actor) = filter.actor {
+            let placeholder = filters.param(actor);
+            filters.clause(format!("actor={}", placeholder));
+        }
+        if let Some(operation) = &filter.operation {
+            let placeholder = filters.param(operation.clone());
+            filters.clause(format!("operation={}", placeholder));
+        }
+        if let Some(target_type) = &filter.target_type {
+            let placeholder = filters.param(target_type.clone());
+            filters.clause(format!("target_type={}", placeholder));
+        }
+        if let Some(target_id) = &filter.target_id {
+            let placeholder = filters.param(target_id.clone());
+            filters.clause(format!("target_id={}", placeholder));
+        }
+
+        self.list_page("audit_log", AUDIT_COLUMNS, "bigint", options, filters, |row| AuditEntry::from(row))
+    }
+}
@@ -0,0 +1,95 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+use postgres::Row;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// heartbeats older than this are dropped whenever a station reports in
+const HEARTBEAT_RETENTION_DAYS: i32 = 7;
+
+/// `station/heartbeats` returns at most this many of the newest heartbeats
+const HEARTBEAT_LIST_LIMIT: i64 = 1000;
+
+/// what a receiver reports about itself, counters are whatever the station sent
+#[derive(Serialize, Debug, Clone)]
+pub struct Heartbeat {
+    pub station: Uuid,
+    pub received_at: DateTime<Utc>,
+    /// seconds since the receiver software started
+    pub uptime: u64,
+    pub version: String,
+    /// telegrams received since the receiver software started
+    pub telegrams: u64,
+}
+
+impl From<&Row> for Heartbeat {
+    fn from(row: &Row) -> Self {
+        Heartbeat {
+            station: row.get(0),
+            received_at: row.get(1),
+            uptime: row.get::<usize, i64>(2) as u64,
+            version: row.get(3),
+            telegrams: row.get::<usize, i64>(4) as u64,
+        }
+    }
+}
+
+fn counter(field: &str, value: u64) -> Result<i64, ServiceError> {
+    i64::try_from(value).map_err(|_| ServiceError::Validation(format!("{} is out of range", field)))
+}
+
+impl DataBaseConnection {
+    /// stores the heartbeat and bumps `last_seen` of the station, counters have to fit into a bigint
+    pub fn record_heartbeat(&mut self, heartbeat: &Heartbeat) -> Result<(), ServiceError> {
+        let uptime = counter("uptime", heartbeat.uptime)?;
+        let telegrams = counter("telegrams", heartbeat.telegrams)?;
+
+        let mut transaction = self.atomic()?;
+
+        transaction.execute(
+            "INSERT INTO station_heartbeats (station, received_at, uptime, version, telegrams) VALUES ($1, $2, $3, $4, $5)",
+            &[
+                &heartbeat.station,
+                &heartbeat.received_at,
+                &uptime,
+                &heartbeat.version,
+                &telegrams,
+            ],
+        )?;
+        transaction.execute(
+            "UPDATE stations SET last_seen=$1 WHERE id=$2",
+            &[&heartbeat.received_at, &heartbeat.station],
+        )?;
+        transaction.execute(
+            "DELETE FROM station_heartbeats WHERE station=$1 AND received_at < now() - make_interval(days => $2)",
+            &[&heartbeat.station, &HEARTBEAT_RETENTION_DAYS],
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn list_heartbeats(&mut self, station: &Uuid) -> Result<Vec<Heartbeat>, ServiceError> {
+        let data = self.postgres.query(
+            "SELECT station, received_at, uptime, version, telegrams FROM station_heartbeats
+             WHERE station=$1 ORDER BY received_at DESC LIMIT $2",
+            &[station, &HEARTBEAT_LIST_LIMIT],
+        )?;
+
+        Ok(data.iter().map(Heartbeat::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_beyond_a_bigint_are_rejected() {
+        assert_eq!(counter("uptime", 3600).ok(), Some(3600));
+        assert_eq!(counter("uptime", i64::MAX as u64).ok(), Some(i64::MAX));
+        assert!(matches!(counter("telegrams", i64::MAX as u64 + 1), Err(ServiceError::Validation(_))));
+        assert!(matches!(counter("telegrams", u64::MAX), Err(ServiceError::Validation(_))));
+    }
+}
@@ -0,0 +1,43 @@
+use super::{DataBaseConnection, ServiceError, User};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+impl DataBaseConnection {
+    /// stores the hash of an invite, with an `email` only that address can register with it
+    pub fn create_invite(
+        &mut self,
+        token_hash: &str,
+        created_by: &Uuid,
+        email: Option<&str>,
+        expires_at: &DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        self.postgres.execute("DELETE FROM invites WHERE expires_at < now()", &[])?;
+        self.postgres.execute(
+            "INSERT INTO invites (token_hash, created_by, email, expires_at) VALUES ($1, $2, $3, $4)",
+            &[&token_hash, created_by, &email, expires_at],
+        )?;
+
+        Ok(())
+    }
+
+    /// uses the invite up and creates the account in one transaction, a failed insert keeps the
+    /// invite, an invite bound to the address of the user marks it as verified
+    pub fn create_invited_user(&mut self, user: &mut User, token_hash: &str) -> Result<(), ServiceError> {
+        let mut transaction = self.atomic()?;
+
+        let row = transaction
+            .query_opt(
+                "DELETE FROM invites WHERE token_hash=$1 AND expires_at > now()
+                    AND (email IS NULL OR lower(email)=lower($2)) RETURNING email",
+                &[&token_hash, &user.email],
+            )?
+            .ok_or_else(|| ServiceError::Validation(String::from("invalid or expired invite")))?;
+        user.email_verified |= row.get::<usize, Option<String>>(0).is_some();
+
+        super::insert_user(&mut *transaction, user)?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+use postgres::types::ToSql;
+use postgres::Row;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 500;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    Name,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// accepts a value either as itself or as text, query strings of the http api only carry text
+/// and which of their values are numbers only the request type knows
+pub fn text_or_value<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw<T> {
+        Value(T),
+        Text(String),
+    }
+
+    match Option::<Raw<T>>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Raw::Value(value)) => Ok(Some(value)),
+        Some(Raw::Text(text)) => text.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// pagination, sorting and the filters every list operation understands
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ListOptions {
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    #[serde(default, deserialize_with = "text_or_value")]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// case insensitive substring of the name
+    pub name: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// number of rows matching the filters over all pages
+    pub total: i64,
+    /// pass as `cursor` to get the next page, missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// WHERE clause assembled from optional filters, values are always bound as parameters
+#[derive(Default)]
+pub struct Filters {
+    clauses: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync>>,
+}
+
+impl Filters {
+    pub fn new(options: &ListOptions) -> Filters {
+        let mut filters = Filters::default();
+
+        if let Some(name) = &options.name {
+            let escaped = name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            let placeholder = filters.param(format!("%{}%", escaped));
+            filters.clause(format!("name ILIKE {}", placeholder));
+        }
+
+        if let Some(created_after) = options.created_after {
+            let placeholder = filters.param(created_after);
+            filters.clause(format!("created_at > {}", placeholder));
+        }
+
+        filters
+    }
+
+    /// binds a value and returns its placeholder
+    pub fn param<T: ToSql + Sync + 'static>(&mut self, value: T) -> String {
+        self.params.push(Box::new(value));
+        format!("${}", self.params.len())
+    }
+
+    pub fn clause(&mut self, clause: String) {
+        self.clauses.push(clause);
+    }
+
+    fn where_clause(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.clauses.join(" AND "))
+        }
+    }
+
+    fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|param| param.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+/// the cursor is the sort value and id of the last row of a page, both as text
+fn encode_cursor(value: &str, id: &str) -> String {
+    base64::encode_config(serde_json::to_vec(&(value, id)).unwrap(), base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor(cursor: &str) -> Result<(String, String), ServiceError> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| ServiceError::Validation(String::from("invalid cursor")))
+}
+
+fn page_limit(options: &ListOptions) -> usize {
+    options.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT) as usize
+}
+
+/// SELECT of one page, rows after the cursor are added to the filters, one row more than
+/// `limit` is fetched to know whether another page follows
+fn page_query(
+    table: &str,
+    columns: &str,
+    id_type: &str,
+    options: &ListOptions,
+    filters: &mut Filters,
+    limit: usize,
+) -> Result<String, ServiceError> {
+    let (sort, sort_type) = match options.sort {
+        SortField::CreatedAt => ("created_at", "timestamptz"),
+        SortField::Name => ("name", "text"),
+    };
+    let (direction, comparison) = match options.order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+
+    if let Some(cursor) = &options.cursor {
+        let (value, id) = decode_cursor(cursor)?;
+        let value = filters.param(value);
+        let id = filters.param(id);
+        filters.clause(format!(
+            "({}, id) {} ({}::text::{}, {}::text::{})",
+            sort, comparison, value, sort_type, id, id_type
+        ));
+    }
+
+    Ok(format!(
+        "SELECT {}, {}::text AS cursor_value, id::text AS cursor_id FROM {}{} ORDER BY {} {}, id {} LIMIT {}",
+        columns,
+        sort,
+        table,
+        filters.where_clause(),
+        sort,
+        direction,
+        direction,
+        limit + 1
+    ))
+}
+
+impl DataBaseConnection {
+    /// keyset paginated SELECT of `columns` from `table`, ordered by the sort field and the id
+    /// as tie breaker, `id_type` is the sql type of the id column
+    pub(super) fn list_page<T>(
+        &mut self,
+        table: &str,
+        columns: &str,
+        id_type: &str,
+        options: &ListOptions,
+        mut filters: Filters,
+        map: impl Fn(&Row) -> T,
+    ) -> Result<Page<T>, ServiceError> {
+        let limit = page_limit(options);
+
+        let total: i64 = self
+            .postgres
+            .query_one(
+                &format!("SELECT count(*) FROM {}{}", table, filters.where_clause()),
+                &filters.params(),
+            )?
+            .get(0);
+
+        let query = page_query(table, columns, id_type, options, &mut filters, limit)?;
+        let mut rows = self.postgres.query(&query, &filters.params())?;
+
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|row| {
+                let value: String = row.get(row.len() - 2);
+                let id: String = row.get(row.len() - 1);
+                encode_cursor(&value, &id)
+            })
+        } else {
+            None
+        };
+
+        Ok(Page {
+            items: rows.iter().map(map).collect(),
+            total,
+            next_cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        for (value, id) in [
+            ("2022-05-01 12:00:00+00", "969cdf1a-0b5e-4c36-8a3e-2d3b0f4f6f10"),
+            ("name with \"quotes\", commas and ünïcode", "7"),
+            ("", ""),
+        ] {
+            let cursor = encode_cursor(value, id);
+            assert!(!cursor.contains(['+', '/', '=']), "{} is not url safe", cursor);
+            assert_eq!(decode_cursor(&cursor).unwrap(), (value.to_string(), id.to_string()));
+        }
+    }
+
+    #[test]
+    fn broken_cursors_are_validation_errors() {
+        let not_a_pair = base64::encode_config(b"[1, 2, 3]", base64::URL_SAFE_NO_PAD);
+        for cursor in ["", "not base64!", "bm90IGpzb24", not_a_pair.as_str()] {
+            assert!(matches!(decode_cursor(cursor), Err(ServiceError::Validation(_))), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn limit_defaults_and_is_clamped() {
+        let limit = |limit| page_limit(&ListOptions { limit, ..Default::default() });
+        assert_eq!(limit(None), DEFAULT_LIMIT as usize);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(20)), 20);
+        assert_eq!(limit(Some(MAX_LIMIT + 1)), MAX_LIMIT as usize);
+    }
+
+    #[test]
+    fn first_page_fetches_one_row_more_than_the_limit() {
+        let options = ListOptions::default();
+        let mut filters = Filters::new(&options);
+        let query = page_query("regions", "id, name", "integer", &options, &mut filters, 10).unwrap();
+
+        assert_eq!(
+            query,
+            "SELECT id, name, created_at::text AS cursor_value, id::text AS cursor_id FROM regions \
+             ORDER BY created_at ASC, id ASC LIMIT 11"
+        );
+        assert!(filters.params().is_empty());
+    }
+
+    #[test]
+    fn cursor_continues_after_the_last_row_in_sort_order() {
+        let options = ListOptions {
+            cursor: Some(encode_cursor("Dresden", "3")),
+            sort: SortField::Name,
+            order: SortOrder::Desc,
+            name: Some(String::from("d")),
+            ..Default::default()
+        };
+        let mut filters = Filters::new(&options);
+        let query = page_query("regions", "id, name", "integer", &options, &mut filters, 10).unwrap();
+
+        assert_eq!(
+            query,
+            "SELECT id, name, name::text AS cursor_value, id::text AS cursor_id FROM regions \
+             WHERE name ILIKE $1 AND (name, id) < ($2::text::text, $3::text::integer) \
+             ORDER BY name DESC, id DESC LIMIT 11"
+        );
+        assert_eq!(filters.params().len(), 3);
+    }
+
+    #[test]
+    fn name_filter_escapes_like_wildcards() {
+        let options = ListOptions {
+            name: Some(String::from("50%_a\\b")),
+            ..Default::default()
+        };
+        let filters = Filters::new(&options);
+        assert_eq!(filters.where_clause(), " WHERE name ILIKE $1");
+        assert_eq!(format!("{:?}", filters.params()[0]), format!("{:?}", "%50\\%\\_a\\\\b%"));
+    }
+
+    #[test]
+    fn page_query_refuses_broken_cursors() {
+        let options = ListOptions {
+            cursor: Some(String::from("garbage")),
+            ..Default::default()
+        };
+        let mut filters = Filters::new(&options);
+        assert!(matches!(
+            page_query("regions", "id", "integer", &options, &mut filters, 10),
+            Err(ServiceError::Validation(_))
+        ));
+    }
+}
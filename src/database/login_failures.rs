@@ -0,0 +1,58 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+
+impl DataBaseConnection {
+    /// the latest lock of any of the keys that is still running
+    pub fn login_locked_until(&mut self, keys: &[String]) -> Result<Option<DateTime<Utc>>, ServiceError> {
+        let row = self.postgres.query_one(
+            "SELECT max(locked_until) FROM login_failures WHERE key = ANY($1) AND locked_until > now()",
+            &[&keys],
+        )?;
+
+        Ok(row.get(0))
+    }
+
+    /// counts a failed login for the key and returns the failures so far, failures older than
+    /// `window` seconds are forgotten
+    pub fn record_login_failure(&mut self, key: &str, window: f64) -> Result<i32, ServiceError> {
+        // piggyback the cleanup so counters of long gone attackers do not pile up
+        self.postgres.execute(
+            "DELETE FROM login_failures WHERE last_failure < now() - make_interval(secs => $1)
+             AND (locked_until IS NULL OR locked_until < now())",
+            &[&window],
+        )?;
+
+        let row = self.postgres.query_one(
+            "INSERT INTO login_failures (key, failures, last_failure) VALUES ($1, 1, now())
+             ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_failures.last_failure < now() - make_interval(secs => $2) THEN 1
+                    ELSE login_failures.failures + 1
+                END,
+                last_failure = now()
+             RETURNING failures",
+            &[&key, &window],
+        )?;
+
+        Ok(row.get(0))
+    }
+
+    pub fn lock_login(&mut self, key: &str, until: &DateTime<Utc>) -> Result<(), ServiceError> {
+        self.postgres.execute(
+            "UPDATE login_failures SET locked_until=$2 WHERE key=$1",
+            &[&key, until],
+        )?;
+
+        Ok(())
+    }
+
+    /// returns whether there was anything to clear
+    pub fn clear_login_failures(&mut self, key: &str) -> Result<bool, ServiceError> {
+        let deleted = self
+            .postgres
+            .execute("DELETE FROM login_failures WHERE key=$1", &[&key])?;
+
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,288 @@
+use super::DataBaseConnection;
+
+use std::fmt;
+
+/// one step of the schema history, versions have to be strictly increasing and a migration
+/// must never be edited once it was released, add a new one instead
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// all migrations known to this binary in the order they are applied
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        // IF NOT EXISTS so deployments which still have the tables from create_tables are adopted
+        sql: "CREATE TABLE IF NOT EXISTS users (
+                id              UUID PRIMARY KEY,
+                name            TEXT NOT NULL,
+                email           TEXT NOT NULL,
+                password        VARCHAR(100) NOT NULL,
+                role            INT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS regions (
+                id              SERIAL PRIMARY KEY,
+                name            TEXT NOT NULL,
+                transport_company TEXT NOT NULL,
+                frequency       BIGINT NOT NULL,
+                protocol        TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS stations (
+                id              UUID PRIMARY KEY,
+                token           VARCHAR(32),
+                name            TEXT NOT NULL,
+                lat             DOUBLE PRECISION NOT NULL,
+                lon             DOUBLE PRECISION NOT NULL,
+                region          SERIAL REFERENCES regions(id) NOT NULL,
+                owner           UUID REFERENCES users(id) NOT NULL,
+                approved        BOOLEAN NOT NULL
+            );",
+    },
+    Migration {
+        version: 2,
+        name: "unique user names",
+        sql: "ALTER TABLE users ADD CONSTRAINT users_name_key UNIQUE (name);",
+    },
+    Migration {
+        version: 3,
+        name: "persistent sessions",
+        sql: "CREATE TABLE sessions (
+                id              UUID PRIMARY KEY,
+                token_hash      CHAR(64) NOT NULL UNIQUE,
+                owner           UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                created_at      TIMESTAMPTZ NOT NULL,
+                expires_at      TIMESTAMPTZ NOT NULL,
+                last_used       TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX sessions_owner ON sessions (owner);",
+    },
+    Migration {
+        version: 4,
+        name: "unbounded password hashes",
+        // argon2 hashes grow with their parameters and do not reliably fit into 100 characters
+        sql: "ALTER TABLE users ALTER COLUMN password TYPE TEXT;",
+    },
+    Migration {
+        version: 5,
+        name: "hashed station tokens",
+        // hashes the existing plaintext tokens in place so running stations keep working
+        sql: "ALTER TABLE stations ADD COLUMN token_hash CHAR(64);
+            UPDATE stations SET token_hash = encode(sha256(convert_to(token, 'UTF8')), 'hex') WHERE token IS NOT NULL;
+            ALTER TABLE stations DROP COLUMN token;",
+    },
+    Migration {
+        version: 6,
+        name: "station token rotation",
+        sql: "ALTER TABLE stations ADD COLUMN token_created_at TIMESTAMPTZ DEFAULT now();
+            ALTER TABLE stations ADD COLUMN previous_token_hash CHAR(64);
+            ALTER TABLE stations ADD COLUMN previous_token_created_at TIMESTAMPTZ;
+            ALTER TABLE stations ADD COLUMN previous_token_expires_at TIMESTAMPTZ;",
+    },
+    Migration {
+        version: 7,
+        name: "station heartbeats",
+        sql: "ALTER TABLE stations ADD COLUMN last_seen TIMESTAMPTZ;
+            CREATE TABLE station_heartbeats (
+                id              BIGSERIAL PRIMARY KEY,
+                station         UUID NOT NULL REFERENCES stations(id) ON DELETE CASCADE,
+                received_at     TIMESTAMPTZ NOT NULL,
+                uptime          BIGINT NOT NULL,
+                version         TEXT NOT NULL,
+                telegrams       BIGINT NOT NULL
+            );
+            CREATE INDEX station_heartbeats_station ON station_heartbeats (station, received_at);",
+    },
+    Migration {
+        version: 8,
+        name: "region bounds",
+        sql: "ALTER TABLE regions ADD COLUMN bounds JSONB;
+            ALTER TABLE stations ADD COLUMN location_review BOOLEAN NOT NULL DEFAULT false;",
+    },
+    Migration {
+        version: 9,
+        name: "creation timestamps",
+        // rows that existed before this migration all get the time it ran
+        sql: "ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+            ALTER TABLE regions ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+            ALTER TABLE stations ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();",
+    },
+    Migration {
+        version: 10,
+        name: "station review workflow",
+        sql: "ALTER TABLE stations ADD COLUMN review_state TEXT NOT NULL DEFAULT 'pending';
+            UPDATE stations SET review_state='approved' WHERE approved;
+            CREATE TABLE station_reviews (
+                id              BIGSERIAL PRIMARY KEY,
+                station         UUID NOT NULL REFERENCES stations(id) ON DELETE CASCADE,
+                reviewer        UUID REFERENCES users(id) ON DELETE SET NULL,
+                from_state      TEXT NOT NULL,
+                to_state        TEXT NOT NULL,
+                comment         TEXT,
+                created_at      TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX station_reviews_station ON station_reviews (station, created_at);",
+    },
+    Migration {
+        version: 11,
+        name: "audit log",
+        sql: "CREATE TABLE audit_log (
+                id              BIGSERIAL PRIMARY KEY,
+                created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+                actor           UUID,
+                operation       TEXT NOT NULL,
+                target_type     TEXT,
+                target_id       TEXT,
+                diff            JSONB,
+                address         TEXT
+            );
+            CREATE INDEX audit_log_created_at ON audit_log (created_at, id);
+            CREATE INDEX audit_log_target ON audit_log (target_type, target_id);
+            CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+            BEGIN
+                RAISE EXCEPTION 'audit_log is append-only';
+            END;
+            $$ LANGUAGE plpgsql;
+            CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE OR TRUNCATE ON audit_log
+                FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();",
+    },
+    Migration {
+        version: 12,
+        name: "login throttling",
+        sql: "CREATE TABLE login_failures (
+                key             TEXT PRIMARY KEY,
+                failures        INT NOT NULL,
+                last_failure    TIMESTAMPTZ NOT NULL,
+                locked_until    TIMESTAMPTZ
+            );",
+    },
+    Migration {
+        version: 13,
+        name: "email verification",
+        sql: "ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
+            UPDATE users SET email_verified=true;
+            CREATE TABLE user_tokens (
+                token_hash      TEXT PRIMARY KEY,
+                owner           UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                purpose         TEXT NOT NULL,
+                expires_at      TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX user_tokens_owner ON user_tokens (owner, purpose);",
+    },
+    Migration {
+        version: 14,
+        name: "two-factor authentication",
+        sql: "ALTER TABLE users ADD COLUMN totp_secret TEXT;
+            ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false;
+            ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+            CREATE TABLE totp_recovery_codes (
+                code_hash       TEXT PRIMARY KEY,
+                owner           UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE
+            );
+            CREATE INDEX totp_recovery_codes_owner ON totp_recovery_codes (owner);
+            CREATE TABLE role_policies (
+                role            INT PRIMARY KEY,
+                require_totp    BOOLEAN NOT NULL DEFAULT false
+            );",
+    },
+    Migration {
+        version: 15,
+        name: "registration invites",
+        sql: "CREATE TABLE invites (
+                token_hash      TEXT PRIMARY KEY,
+                created_by      UUID REFERENCES users(id) ON DELETE SET NULL,
+                email           TEXT,
+                created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+                expires_at      TIMESTAMPTZ NOT NULL
+            );",
+    },
+];
+
+// arbitrary key for pg_advisory_xact_lock so two instances never migrate at the same time
+const MIGRATION_LOCK: i64 = 0x636c69636b79;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Postgres(postgres::Error),
+    DatabaseTooNew { database: i32, binary: i32 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Postgres(e) => write!(f, "postgres error while migrating: {}", e),
+            MigrationError::DatabaseTooNew { database, binary } => write!(
+                f,
+                "database schema is at version {} but this binary only knows up to version {}, refusing to start",
+                database, binary
+            ),
+        }
+    }
+}
+
+impl From<postgres::Error> for MigrationError {
+    fn from(e: postgres::Error) -> Self {
+        MigrationError::Postgres(e)
+    }
+}
+
+/// version the schema has after all migrations of this binary are applied
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+impl DataBaseConnection {
+    fn create_version_table(&mut self) -> Result<(), postgres::Error> {
+        self.postgres.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version         INT PRIMARY KEY,
+                name            TEXT NOT NULL,
+                applied_at      TIMESTAMP NOT NULL DEFAULT now()
+            )",
+        )
+    }
+
+    /// returns the version the database is currently at, 0 for a fresh database
+    pub fn schema_version(&mut self) -> Result<i32, MigrationError> {
+        self.create_version_table()?;
+        let row = self
+            .postgres
+            .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?;
+        Ok(row.get(0))
+    }
+
+    /// applies every migration newer than the database, each one in its own transaction
+    pub fn migrate(&mut self) -> Result<i32, MigrationError> {
+        self.create_version_table()?;
+
+        let binary = latest_version();
+        let database = self.schema_version()?;
+        if database > binary {
+            return Err(MigrationError::DatabaseTooNew { database, binary });
+        }
+
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version > database) {
+            let mut transaction = self.postgres.transaction()?;
+            transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+
+            // another instance may have applied this one while we waited for the lock
+            let applied = transaction
+                .query("SELECT 1 FROM schema_version WHERE version=$1", &[&migration.version])?;
+            if !applied.is_empty() {
+                continue;
+            }
+
+            println!("Applying migration {}: {}", migration.version, migration.name);
+            transaction.batch_execute(migration.sql)?;
+            transaction.execute(
+                "INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )?;
+            transaction.commit()?;
+        }
+
+        self.schema_version()
+    }
+}
@@ -1,16 +1,53 @@
 extern crate postgres;
 
+mod audit;
+mod heartbeats;
+mod invites;
+mod listing;
+mod login_failures;
+mod migrations;
+mod reviews;
+mod sessions;
+mod station_tokens;
+mod totp;
+mod user_tokens;
+
+pub use audit::{AuditEntry, AuditFilter};
+pub use heartbeats::Heartbeat;
+pub use listing::{text_or_value, ListOptions, Page};
+pub use migrations::latest_version;
+pub use reviews::{Review, ReviewState};
+pub use sessions::Session;
+pub use station_tokens::StationTokens;
+pub use user_tokens::TokenPurpose;
+
 use postgres::{Client, NoTls, config::SslMode };
+use postgres::types::Json;
+use postgres::GenericClient;
+use postgres::Row;
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
 use serde::ser::{SerializeStruct, Serializer};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::clone::Clone;
 use std::cmp::PartialEq;
 use std::env;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use uuid::Uuid;
 
+use super::ServiceError;
+use listing::Filters;
+
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
 pub enum Role {
     User = 6,
+    /// accounts of the telegram collecting services, allowed to verify station tokens
+    Service = 5,
+    /// reviews and looks after stations but can not touch regions or users
+    Moderator = 3,
     Administrator = 0,
 }
 
@@ -19,6 +56,8 @@ impl Role {
     pub fn from(role: u32) -> Role {
         match role {
             0 => Role::Administrator,
+            3 => Role::Moderator,
+            5 => Role::Service,
             _ => Role::User,
         }
     }
@@ -26,6 +65,8 @@ impl Role {
     pub fn as_int(&self) -> u32 {
         match self {
             Role::Administrator => 0,
+            Role::Moderator => 3,
+            Role::Service => 5,
             _ => 6,
         }
     }
@@ -39,13 +80,14 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub role: Role,
+    /// set once the user followed the verification mail
+    pub email_verified: bool,
+    /// login asks for a one-time code after the password
+    pub totp_enabled: bool,
 }
 
-impl User {
-    pub fn is_admin(&self) -> bool {
-        self.role == Role::Administrator
-    }
-}
+/// closed ring of `[lon, lat]` pairs in the same order as geojson, the last point connects to the first
+pub type Polygon = Vec<[f64; 2]>;
 
 #[derive(Serialize, Debug)]
 pub struct Region {
@@ -54,21 +96,188 @@ pub struct Region {
     pub transport_company: String,
     pub frequency: u64,
     pub protocol: String,
+    pub bounds: Option<Polygon>,
+}
+
+impl Region {
+    /// ray casting test, regions without bounds contain every point
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let bounds = match &self.bounds {
+            Some(bounds) => bounds,
+            None => return true,
+        };
+
+        let mut inside = false;
+        let mut previous = bounds[bounds.len() - 1];
+        for &current in bounds {
+            let ([x1, y1], [x2, y2]) = (previous, current);
+            if (y1 > lat) != (y2 > lat) && lon < (x2 - x1) * (lat - y1) / (y2 - y1) + x1 {
+                inside = !inside;
+            }
+            previous = current;
+        }
+
+        inside
+    }
 }
 
 pub struct Station {
     pub id: Uuid,
-    pub token: Option<String>,
+    /// sha256 of the station token, never serialized
+    pub token_hash: Option<String>,
     pub name: String,
     pub lat: f64,
     pub lon: f64,
     pub region: u32,
     pub owner: Uuid,
+    /// mirrors `review_state == Approved`, kept as column for the filters
     pub approved: bool,
+    pub review_state: ReviewState,
+    /// time of the last heartbeat
+    pub last_seen: Option<DateTime<Utc>>,
+    /// derived from `last_seen` by the endpoints, the database always reports false
+    pub online: bool,
+    /// the station lies outside the bounds of its region and waits for an administrator
+    pub location_review: bool,
+}
+
+/// station specific filters of `station/list`
+#[derive(Debug, Default)]
+pub struct StationFilter {
+    pub owner: Option<Uuid>,
+    pub region: Option<u32>,
+    pub approved: Option<bool>,
+}
+
+const STATION_COLUMNS: &str = "id, name, lat, lon, region, owner, approved, last_seen, location_review, review_state";
+const REGION_COLUMNS: &str = "id, name, transport_company, frequency, protocol, bounds";
+
+impl From<&Row> for Station {
+    fn from(row: &Row) -> Self {
+        Station {
+            id: row.get(0),
+            token_hash: None,
+            name: row.get(1),
+            lat: row.get(2),
+            lon: row.get(3),
+            region: row.get::<usize, i32>(4) as u32,
+            owner: row.get(5),
+            approved: row.get(6),
+            last_seen: row.get(7),
+            online: false,
+            location_review: row.get(8),
+            review_state: ReviewState::from(row.get::<usize, &str>(9)),
+        }
+    }
+}
+
+impl From<&Row> for Region {
+    fn from(row: &Row) -> Self {
+        Region {
+            id: row.get::<usize, i32>(0) as u32,
+            name: row.get(1),
+            transport_company: row.get(2),
+            frequency: row.get::<usize, i64>(3) as u64,
+            protocol: row.get(4),
+            bounds: row.get::<usize, Option<Json<Polygon>>>(5).map(|bounds| bounds.0),
+        }
+    }
 }
 
+type Pooled = PooledConnection<PostgresConnectionManager<NoTls>>;
+
+/// shared handle to the postgres connection pool, cheap to clone into every connection task,
+/// every clone can run one request in a transaction of its own
+pub struct DataBasePool {
+    pool: Pool<PostgresConnectionManager<NoTls>>,
+    /// connection of the request transaction while no `DataBaseConnection` borrows it
+    request: Arc<Mutex<Option<Pooled>>>,
+    /// set between `begin` and `end`
+    in_request: Arc<AtomicBool>,
+}
+
+impl Clone for DataBasePool {
+    fn clone(&self) -> Self {
+        DataBasePool {
+            pool: self.pool.clone(),
+            request: Arc::default(),
+            in_request: Arc::default(),
+        }
+    }
+}
+
+/// one connection checked out of the pool, it goes back to the pool when dropped, or to the
+/// request it was taken from
 pub struct DataBaseConnection {
-    postgres: Client,
+    postgres: Lease,
+}
+
+struct Lease {
+    connection: Option<Pooled>,
+    request: Option<Arc<Mutex<Option<Pooled>>>>,
+}
+
+impl Deref for Lease {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Lease {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(request) = &self.request {
+            *request.lock().unwrap() = self.connection.take();
+        }
+    }
+}
+
+/// statements that are applied together or not at all, inside a request transaction they
+/// become a savepoint so a failing block does not take the rest of the request with it
+pub struct Atomic<'a> {
+    client: &'a mut Client,
+    nested: bool,
+    done: bool,
+}
+
+impl Atomic<'_> {
+    pub fn commit(mut self) -> Result<(), postgres::Error> {
+        self.done = true;
+        self.client
+            .batch_execute(if self.nested { "RELEASE SAVEPOINT atomic" } else { "COMMIT" })
+    }
+}
+
+impl Deref for Atomic<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client
+    }
+}
+
+impl DerefMut for Atomic<'_> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client
+    }
+}
+
+impl Drop for Atomic<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let rollback = if self.nested { "ROLLBACK TO SAVEPOINT atomic" } else { "ROLLBACK" };
+            if let Err(e) = self.client.batch_execute(rollback) {
+                println!("could not roll back {:?}", e);
+            }
+        }
+    }
 }
 
 impl Serialize for User {
@@ -76,11 +285,13 @@ impl Serialize for User {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("User", 4)?;
+        let mut s = serializer.serialize_struct("User", 6)?;
         s.serialize_field("id", &self.id.to_string())?;
         s.serialize_field("name", &self.name)?;
         s.serialize_field("email", &self.email)?;
         s.serialize_field("password", &self.password)?;
+        s.serialize_field("email_verified", &self.email_verified)?;
+        s.serialize_field("totp_enabled", &self.totp_enabled)?;
         s.end()
     }
 }
@@ -90,8 +301,7 @@ impl Serialize for Station {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_struct("Station", 8).unwrap();
-        s.serialize_field("token", &self.token)?;
+        let mut s = serializer.serialize_struct("Station", 11).unwrap();
         s.serialize_field("id", &self.id)?;
         s.serialize_field("name", &self.name)?;
         s.serialize_field("lat", &self.lat)?;
@@ -99,490 +309,513 @@ impl Serialize for Station {
         s.serialize_field("region", &self.region)?;
         s.serialize_field("owner", &self.owner.to_string())?;
         s.serialize_field("approved", &self.approved)?;
+        s.serialize_field("review_state", &self.review_state)?;
+        s.serialize_field("last_seen", &self.last_seen)?;
+        s.serialize_field("online", &self.online)?;
+        s.serialize_field("location_review", &self.location_review)?;
         s.end()
     }
 }
 
-impl DataBaseConnection {
-    pub fn new() -> DataBaseConnection {
-        let default_postgres_host = String::from("localhost:5433");
-        let default_postgres_port = String::from("5432");
-
-        let postgres_host = format!(
-            "postgres://dvbdump:{}@{}:{}/dvbdump",
-            env::var("POSTGRES_PASSWORD").unwrap(),
-            env::var("POSTGRES_HOST").unwrap_or(default_postgres_host.clone()),
-            env::var("POSTGRES_PORT").unwrap_or(default_postgres_port.clone())
-        );
-
-        println!("Connecting to Database at {}", postgres_host);
-        let mut database = DataBaseConnection {
-            postgres: Client::configure()
-                .user("dvbdump")
-                .password(env::var("POSTGRES_PASSWORD").unwrap())
-                .dbname("dvbdump")
-                .host(&env::var("POSTGRES_HOST").unwrap_or(default_postgres_host))
-                .port(env::var("POSTGRES_PORT").unwrap_or(default_postgres_port).parse::<u16>().unwrap())
-                .ssl_mode(SslMode::Disable)
-                .connect(NoTls).unwrap(),
-        };
-        println!("Creating Database Tables !");
-        database.create_tables();
-
-        return database;
-    }
-
-    pub  fn create_tables(&mut self) {
-        match self.postgres
-            .execute(
-                "CREATE TABLE users (
-                    id              UUID PRIMARY KEY,
-                    name            TEXT NOT NULL,
-                    email           TEXT NOT NULL,
-                    password        VARCHAR(100) NOT NULL,
-                    role            INT NOT NULL
-                  )",
-                &[],
-        ) {
-            Err(e) => {println!("Did not create table user maybe it already exists! {:?}", e); },
-            _ => {}
-        }
+/// shared by plain registrations and the ones that use up an invite in the same transaction
+fn insert_user(client: &mut impl GenericClient, user: &User) -> Result<(), ServiceError> {
+    client.execute(
+        "INSERT INTO users (id, name, email, password, role, email_verified) VALUES ($1, $2, $3, $4, $5, $6)",
+        &[
+            &user.id,
+            &user.name,
+            &user.email,
+            &user.password,
+            &(user.role.as_int() as i32),
+            &user.email_verified,
+        ],
+    )?;
+    Ok(())
+}
 
-        match self.postgres
-            .execute(
-                "CREATE TABLE regions (
-                    id              SERIAL PRIMARY KEY,
-                    name            TEXT NOT NULL,
-                    transport_company TEXT NOT NULL,
-                    frequency       BIGINT NOT NULL,
-                    protocol        TEXT NOT NULL
-                  )",
-                &[],
-        ) {
-            Err(e) => {println!("Did not create table regions maybe it already exists! {:?}", e); },
-            _ => {}
+impl DataBasePool {
+    pub fn new(pool_size: u32) -> DataBasePool {
+        let postgres_host = env::var("POSTGRES_HOST").unwrap_or(String::from("localhost:5433"));
+        let postgres_port = env::var("POSTGRES_PORT").unwrap_or(String::from("5432"));
+
+        // the password stays out of the log
+        println!("Connecting to Database at {}:{} with {} connections", postgres_host, postgres_port, pool_size);
+        let mut config = Client::configure();
+        config
+            .user("dvbdump")
+            .password(env::var("POSTGRES_PASSWORD").unwrap())
+            .dbname("dvbdump")
+            .host(&postgres_host)
+            .port(postgres_port.parse::<u16>().unwrap())
+            .ssl_mode(SslMode::Disable);
+
+        DataBasePool {
+            pool: Pool::builder()
+                .max_size(pool_size)
+                .build(PostgresConnectionManager::new(config, NoTls))
+                .unwrap(),
+            request: Arc::default(),
+            in_request: Arc::default(),
         }
+    }
 
-        match self.postgres
-            .execute(
-                "CREATE TABLE stations (
-                    id              UUID PRIMARY KEY,
-                    token           VARCHAR(32),
-                    name            TEXT NOT NULL,
-                    lat             DOUBLE PRECISION NOT NULL,
-                    lon             DOUBLE PRECISION NOT NULL,
-                    region          SERIAL REFERENCES regions(id) NOT NULL,
-                    owner           UUID REFERENCES users(id) NOT NULL,
-                    approved        BOOLEAN NOT NULL
-                  )",
-                &[],
-        ) {
-            Err(e) => {println!("Did not create table stations maybe it already exists! {:?}", e);},
-            _ => { }
+    /// checks out a connection, blocks until one is free or the pool timeout is hit, during a
+    /// request it is the connection of the request
+    pub fn get(&self) -> Result<DataBaseConnection, r2d2::Error> {
+        if self.in_request.load(Ordering::SeqCst) {
+            let connection = self.request.lock().unwrap().take();
+            // a second connection would not see the uncommitted rows of the request
+            assert!(connection.is_some(), "the connection of the request is borrowed already");
+            return Ok(DataBaseConnection {
+                postgres: Lease {
+                    connection,
+                    request: Some(self.request.clone()),
+                },
+            });
         }
+
+        Ok(DataBaseConnection {
+            postgres: Lease {
+                connection: Some(self.pool.get()?),
+                request: None,
+            },
+        })
     }
 
-    pub  fn query_station(&mut self, token: &Uuid) -> Option<Station> {
-        match self.postgres.query_one(
-            "SELECT token, id, name, lat, lon, region, owner, approved FROM stations WHERE id=$1",
-            &[token],
-        ) {
-            Ok(data) => Some(Station {
-                token: Some(data.get(0)),
-                id: data.get::<usize, Uuid>(1),
-                name: data.get(2),
-                lat: data.get::<usize, f64>(3),
-                lon: data.get::<usize, f64>(4),
-                region: data.get::<usize, i32>(5) as u32,
-                owner: data.get::<usize, Uuid>(6),
-                approved: data.get(7),
-            }),
-            Err(e) => {
-                println!("Error from database: query_station {:?}", e);
-                None
-            }
-        }
+    /// starts a transaction that every `get` until `end` takes part in
+    pub fn begin(&self) -> Result<(), ServiceError> {
+        let mut connection = self.pool.get()?;
+        connection.batch_execute("BEGIN")?;
+
+        *self.request.lock().unwrap() = Some(connection);
+        self.in_request.store(true, Ordering::SeqCst);
+        Ok(())
     }
 
-    pub  fn query_region(&mut self, id: &u32) -> Option<Region> {
-        match self.postgres.query_one(
-            "SELECT id, name, transport_company, frequency, protocol FROM regions WHERE id=$1",
-            &[&(*id as i32)],
-        ) {
-            Ok(data) => Some(Region {
-                id: data.get::<usize, i32>(0) as u32,
-                name: data.get(1),
-                transport_company: data.get(2),
-                frequency: data.get::<usize, i64>(3) as u64,
-                protocol: data.get(4),
-            }),
-            Err(e) => {
-                println!("Error from database: query_region {:?}", e);
-                None
-            }
-        }
+    /// commits or rolls back the transaction of `begin` and returns its connection to the pool
+    pub fn end(&self, commit: bool) -> Result<(), ServiceError> {
+        self.in_request.store(false, Ordering::SeqCst);
+        let mut connection = self
+            .request
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(ServiceError::Internal)?;
+
+        connection.batch_execute(if commit { "COMMIT" } else { "ROLLBACK" })?;
+        Ok(())
     }
+}
 
-    pub  fn query_user(&mut self, name: &String) -> Option<User> {
-        match self.postgres.query_one(
-            "SELECT id, name, email, password, role FROM users WHERE name=$1",
-            &[&name],
-        ) {
-            Ok(data) => {
-                let user_id: Uuid = data.get(0);
-                let role: i32 = data.get(4);
-                println!("data: {:?}", data);
-                Some(User {
-                    id: user_id,
-                    name: data.get(1),
-                    email: data.get(2),
-                    password: data.get(3),
-                    role: Role::from(role as u32),
-                })
-            },
-            Err(e) => {
-                println!("query user {:?}", e);
-                None
-            }
-        }
+impl DataBaseConnection {
+    /// `transaction` that nests into the request transaction
+    pub(crate) fn atomic(&mut self) -> Result<Atomic<'_>, postgres::Error> {
+        let nested = self.postgres.request.is_some();
+        let client = &mut *self.postgres;
+        client.batch_execute(if nested { "SAVEPOINT atomic" } else { "BEGIN" })?;
+
+        Ok(Atomic {
+            client,
+            nested,
+            done: false,
+        })
     }
 
-    pub  fn query_user_by_id(&mut self, id: &Uuid) -> Option<User> {
-        match self.postgres.query_one(
-            "SELECT id, name, email, password FROM users WHERE id=$1",
-            &[id],
-        ) {
-            Ok(data) => Some(User {
-                id: Uuid::parse_str(data.get(0)).unwrap(),
-                name: data.get(1),
-                email: data.get(2),
-                password: data.get(3),
-                role: Role::from(data.get(4)),
-            }),
-            _ => None,
-        }
+    pub fn query_station(&mut self, id: &Uuid) -> Result<Station, ServiceError> {
+        let data = self
+            .postgres
+            .query_opt(
+                "SELECT token_hash, id, name, lat, lon, region, owner, approved, last_seen, location_review, review_state FROM stations WHERE id=$1",
+                &[id],
+            )?
+            .ok_or(ServiceError::NotFound("station"))?;
+
+        Ok(Station {
+            token_hash: data.get(0),
+            id: data.get::<usize, Uuid>(1),
+            name: data.get(2),
+            lat: data.get::<usize, f64>(3),
+            lon: data.get::<usize, f64>(4),
+            region: data.get::<usize, i32>(5) as u32,
+            owner: data.get::<usize, Uuid>(6),
+            approved: data.get(7),
+            last_seen: data.get(8),
+            online: false,
+            location_review: data.get(9),
+            review_state: ReviewState::from(data.get::<usize, &str>(10)),
+        })
     }
-    pub fn check_region_exists(&mut self, id: u32) -> bool {
-        match self
+
+    pub fn query_region(&mut self, id: &u32) -> Result<Region, ServiceError> {
+        let data = self
             .postgres
-            .query("SELECT 1 FROM regions WHERE id=$1", &[&id])
-        {
-            Ok(data) => {data.len() > 0}
-            _ => true,
-        }
+            .query_opt(
+                &format!("SELECT {} FROM regions WHERE id=$1", REGION_COLUMNS),
+                &[&(*id as i32)],
+            )?
+            .ok_or(ServiceError::NotFound("region"))?;
+
+        Ok(Region::from(&data))
     }
 
-    pub fn check_user_exists(&mut self, name: &String) -> bool {
-        match self
+    pub fn query_user(&mut self, name: &String) -> Result<User, ServiceError> {
+        let data = self
             .postgres
-            .query("SELECT 1 FROM users WHERE name=$1", &[name])
-        {
-            Ok(data) => {
-                println!("Users exists: {}", data.len());
-                data.len() > 0
-            },
-            Err(e) => {
-                // illegal state has most likely happend prohibit login
-                println!("Exists error: {:?}", e);
-                true
-            }
-        }
+            .query_opt(
+                "SELECT id, name, email, password, role, email_verified, totp_enabled FROM users WHERE name=$1",
+                &[&name],
+            )?
+            .ok_or(ServiceError::NotFound("user"))?;
+
+        Ok(User {
+            id: data.get(0),
+            name: data.get(1),
+            email: data.get(2),
+            password: data.get(3),
+            role: Role::from(data.get::<usize, i32>(4) as u32),
+            email_verified: data.get(5),
+            totp_enabled: data.get(6),
+        })
     }
-    pub  fn list_stations(
-        &mut self,
-        owner: Option<String>,
-        region: Option<u32>,
-    ) -> Vec<Station> {
-        let mut station_list: Vec<Station> = Vec::new();
-        let argumnet_count = owner.clone().map_or_else(|| 0, |_| 1) + region.map_or_else(|| 0, |_| 1);
-
-        let owner_query = owner.clone().map_or_else(
-            || String::from(""),
-            |_| format!("WHERE owner=${} ", argumnet_count - 1),
-        );
-        let region_query = region.map_or_else(
-            || String::from(""),
-            |_| format!("WHERE region=${}", argumnet_count),
-        );
-
-        let query = format!(
-            "SELECT id, name, lat, lon, region, owner, approved FROM stations {}{}",
-            owner_query, region_query
-        );
-
-        let results;
-
-        println!("Query {}", &query);
-        if owner.is_some() && region.is_some() {
-            results = self
-                .postgres
-                .query(&query, &[&(owner.unwrap()), &(region.unwrap() as i32)]);
-        } else if owner.is_some() {
-            results = self
-                .postgres
-                .query(&query, &[&(owner.unwrap())]);
-        } else if region.is_some() {
-            results = self.postgres.query(&query, &[&(region.unwrap() as i32)]);
-        } else {
-            results = self.postgres.query(&query, &[]);
-        }
-        match results {
-            Ok(data) => {
-                for row in data {
-                    let station_id: Uuid = row.get(0);
-                    let region: i32 = row.get(4);
-                    let owner: Uuid = row.get(5);
-
-                    station_list.push(Station {
-                        id: station_id,
-                        token: None,
-                        name: row.get(1),
-                        lat: row.get(2),
-                        lon: row.get(3),
-                        region: region as u32,
-                        owner: owner,
-                        approved: row.get(6),
-                    });
-                }
-            }
-            Err(e) => {
-                println!("Error on searching stations {:?}",e);
-            }
-        }
 
-        station_list
+    pub fn query_user_by_id(&mut self, id: &Uuid) -> Result<User, ServiceError> {
+        let data = self
+            .postgres
+            .query_opt(
+                "SELECT id, name, email, password, role, email_verified, totp_enabled FROM users WHERE id=$1",
+                &[id],
+            )?
+            .ok_or(ServiceError::NotFound("user"))?;
+
+        Ok(User {
+            id: data.get(0),
+            name: data.get(1),
+            email: data.get(2),
+            password: data.get(3),
+            role: Role::from(data.get::<usize, i32>(4) as u32),
+            email_verified: data.get(5),
+            totp_enabled: data.get(6),
+        })
     }
 
-    pub fn list_regions(&mut self) -> Vec<Region> {
-        let mut results = Vec::new();
-        for row in self
+    pub fn check_region_exists(&mut self, id: u32) -> Result<bool, ServiceError> {
+        let data = self
             .postgres
-            .query(
-                "SELECT id, name, transport_company, frequency, protocol FROM regions",
-                &[],
-            )
-            .unwrap()
-        {
-            results.push(Region {
-                id: (row.get::<usize, i32>(0) as u32),
+            .query("SELECT 1 FROM regions WHERE id=$1", &[&(id as i32)])?;
+        Ok(!data.is_empty())
+    }
+
+    /// every account registered with the address, names are unique but addresses are not
+    pub fn query_users_by_email(&mut self, email: &str) -> Result<Vec<User>, ServiceError> {
+        let data = self.postgres.query(
+            "SELECT id, name, email, password, role, email_verified, totp_enabled FROM users WHERE lower(email)=lower($1)",
+            &[&email],
+        )?;
+
+        Ok(data
+            .iter()
+            .map(|row| User {
+                id: row.get(0),
                 name: row.get(1),
-                transport_company: row.get(2),
-                frequency: row.get::<usize, i64>(3) as u64,
-                protocol: row.get(4),
-            });
-        }
-        results
+                email: row.get(2),
+                password: row.get(3),
+                role: Role::from(row.get::<usize, i32>(4) as u32),
+                email_verified: row.get(5),
+                totp_enabled: row.get(6),
+            })
+            .collect())
     }
 
-    pub fn list_users(&mut self) -> Vec<User> {
-        let mut results = Vec::new();
-        match self
+    pub fn check_user_exists(&mut self, name: &String) -> Result<bool, ServiceError> {
+        let data = self
             .postgres
-            .query(
-                "SELECT id, name, email, role FROM users",
-                &[],
-            ) {
-            Ok(data) => {
-                for row in data {
-                    let user_id: Uuid = row.get(0);
-                    let role: i32 = row.get(3);
-                    results.push(User {
-                        id: user_id,
-                        name: row.get(1),
-                        email: row.get(2),
-                        password: String::from(""),
-                        role: Role::from(role as u32),
-                    });
-                }
-            }
-            _ => {}
+            .query("SELECT 1 FROM users WHERE name=$1", &[name])?;
+        Ok(!data.is_empty())
+    }
+
+    pub fn list_stations(&mut self, filter: &StationFilter, options: &ListOptions) -> Result<Page<Station>, ServiceError> {
+        let mut filters = Filters::new(options);
 
+        if let Some(owner) = filter.owner {
+            let placeholder = filters.param(owner);
+            filters.clause(format!("owner={}", placeholder));
         }
-        results
-    }
-
-
-    pub  fn create_user(&mut self, user: &User) -> bool {
-        println!("create user: {:?}", &user);
-        match self.postgres
-            .execute(
-                "INSERT INTO users (id, name, email, password, role) VALUES ($1, $2, $3, $4, $5)",
-                &[
-                    &user.id,
-                    &user.name,
-                    &user.email,
-                    &user.password,
-                    &(user.role.as_int() as i32),
-                ],
-            ) {
-                Ok(_) => { true }
-                Err(e) => {
-                    println!("Error: {}", e);
-                    false
-                }
+        if let Some(region) = filter.region {
+            let placeholder = filters.param(region as i32);
+            filters.clause(format!("region={}", placeholder));
         }
+        if let Some(approved) = filter.approved {
+            let placeholder = filters.param(approved);
+            filters.clause(format!("approved={}", placeholder));
+        }
+
+        self.list_page("stations", STATION_COLUMNS, "uuid", options, filters, |row| Station::from(row))
     }
 
-    pub fn create_region(&mut self, region: &Region) -> bool {
-        println!("region: {:?}", &region);
-        match self.postgres
-            .execute(
-                "INSERT INTO regions (name, transport_company, frequency, protocol) VALUES ($1, $2, $3, $4)",
-                &[
-                    &region.name,
-                    &region.transport_company,
-                    &(region.frequency as i64),
-                    &region.protocol,
-                ],
-            ) {
-            Ok(respone) => {
-                println!("Response: create region: {:?}", respone);
-                true 
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-                false
-            }
-        }
+    /// every approved station, used for the public export
+    pub fn approved_stations(&mut self) -> Result<Vec<Station>, ServiceError> {
+        let data = self.postgres.query(
+            &format!("SELECT {} FROM stations WHERE approved", STATION_COLUMNS),
+            &[],
+        )?;
+
+        Ok(data.iter().map(Station::from).collect())
+    }
+
+    pub fn list_regions(&mut self, options: &ListOptions) -> Result<Page<Region>, ServiceError> {
+        self.list_page("regions", REGION_COLUMNS, "int", options, Filters::new(options), |row| Region::from(row))
     }
 
-    pub  fn create_station(&mut self, station: &Station) -> bool {
-        match self.postgres.execute(
-            "INSERT INTO stations (id, token, name, lat, lon, region, owner, approved) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    pub fn all_regions(&mut self) -> Result<Vec<Region>, ServiceError> {
+        let data = self
+            .postgres
+            .query(&format!("SELECT {} FROM regions ORDER BY id", REGION_COLUMNS), &[])?;
+
+        Ok(data.iter().map(Region::from).collect())
+    }
+
+    pub fn list_users(&mut self, options: &ListOptions) -> Result<Page<User>, ServiceError> {
+        self.list_page(
+            "users",
+            "id, name, email, role, email_verified, totp_enabled",
+            "uuid",
+            options,
+            Filters::new(options),
+            |row| User {
+                id: row.get(0),
+                name: row.get(1),
+                email: row.get(2),
+                password: String::from(""),
+                role: Role::from(row.get::<usize, i32>(3) as u32),
+                email_verified: row.get(4),
+                totp_enabled: row.get(5),
+            },
+        )
+    }
+
+    pub fn create_user(&mut self, user: &User) -> Result<(), ServiceError> {
+        insert_user(&mut *self.postgres, user)
+    }
+
+    pub fn create_region(&mut self, region: &Region) -> Result<u32, ServiceError> {
+        let row = self.postgres.query_one(
+            "INSERT INTO regions (name, transport_company, frequency, protocol, bounds) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            &[
+                &region.name,
+                &region.transport_company,
+                &(region.frequency as i64),
+                &region.protocol,
+                &region.bounds.as_ref().map(Json),
+            ],
+        )?;
+        Ok(row.get::<usize, i32>(0) as u32)
+    }
+
+    pub fn create_station(&mut self, station: &Station) -> Result<(), ServiceError> {
+        self.postgres.execute(
+            "INSERT INTO stations (id, token_hash, name, lat, lon, region, owner, approved, location_review) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
             &[
                 &station.id,
-                &station.token,
+                &station.token_hash,
                 &station.name,
                 &station.lat,
                 &station.lon,
                 &(station.region as i32),
                 &station.owner,
-                &station.approved
+                &station.approved,
+                &station.location_review,
             ],
-        ) {
-            Ok(_) => { true }
-            Err(e) => {
-                println!("Error: {}", e);
-                false
-            }
-        }
+        )?;
+        Ok(())
     }
 
-    pub  fn first_user(&mut self) -> bool {
-        match self.postgres.query("SELECT 1 FROM users", &[]) {
-            Ok(data) => { data.len() == 0 },
-            Err(_) => false,
-        }
+    pub fn has_administrator(&mut self) -> Result<bool, ServiceError> {
+        let data = self.postgres.query(
+            "SELECT 1 FROM users WHERE role=$1 LIMIT 1",
+            &[&(Role::Administrator.as_int() as i32)],
+        )?;
+        Ok(!data.is_empty())
+    }
+
+    pub fn get_owner_from_station(&mut self, station_id: &Uuid) -> Result<Uuid, ServiceError> {
+        Ok(self.query_station(station_id)?.owner)
     }
 
-    pub  fn is_administrator(&mut self, uid: &Uuid) -> bool {
-        match self
+    pub fn delete_user(&mut self, uid: &Uuid) -> Result<(), ServiceError> {
+        let deleted = self
             .postgres
-            .query_one("SELECT role FROM users WHERE id=$1", &[uid])
-        {
-            Ok(row) => row.get::<usize, i32>(0) == 0,
-            _ => false,
-        }
+            .execute("DELETE FROM users WHERE id=$1", &[uid])?;
+        expect_row(deleted, "user")
     }
 
-    pub  fn get_owner_from_station(&mut self, station_id: &Uuid) -> Option<Uuid> {
-        match self.query_station(station_id){
-            Some(region) => Some(region.owner),
-            _ => None,
-        }
+    pub fn delete_region(&mut self, id: &u32) -> Result<(), ServiceError> {
+        let deleted = self
+            .postgres
+            .execute("DELETE FROM regions WHERE id=$1", &[&(*id as i32)])?;
+        expect_row(deleted, "region")
     }
 
-    pub  fn delete_user(&mut self, uid: &Uuid) -> bool {
-        self.postgres
-            .execute("DELETE FROM users WHERE id=$1", &[uid])
-            .is_ok()
-    }
-
-    pub  fn delete_region(&mut self, id: &u32) -> bool {
-        self.postgres
-            .execute("DELETE FROM users WHERE id=$1", &[id])
-            .is_ok()
-    }
-
-    pub  fn delete_station(&mut self, id: &Uuid) -> bool {
-        self.postgres
-            .execute("DELETE FROM users WHERE id=$1", &[id])
-            .is_ok()
-    }
-
-    pub  fn update_user(&mut self, user: &User) -> bool {
-        match self.postgres
-            .execute(
-                "UPDATE users SET name=$1, email=$2, password=$3, role=$4 WHERE id=$5",
-                &[
-                    &user.name,
-                    &user.email,
-                    &user.password,
-                    &user.role.as_int(),
-                    &user.id.to_string(),
-                ],
-            ) {
-                Err(e) => {
-                    println!("update users {:?}", e);
-                    false
-                }
-                _ => {true}
-            }
+    pub fn delete_station(&mut self, id: &Uuid) -> Result<(), ServiceError> {
+        let deleted = self
+            .postgres
+            .execute("DELETE FROM stations WHERE id=$1", &[id])?;
+        expect_row(deleted, "station")
     }
 
-    pub  fn update_station(&mut self, station: &Station) -> bool {
-        match self.postgres
-            .execute(
-                "UPDATE stations SET name=$1, lat=$2, lon=$3, region=$4 WHERE id=$5",
-                &[
-                    &station.name,
-                    &station.lat,
-                    &station.lon,
-                    &(station.region as i32),
-                    &station.id,
-                ],
-            ) {
-            Err(e) => {
-                println!("update stations {:?}", e);
-                false
-            }
-            _ => {true}
-        }
+    pub fn update_user(&mut self, user: &User) -> Result<(), ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE users SET name=$1, email=$2, password=$3, role=$4, email_verified=$5 WHERE id=$6",
+            &[
+                &user.name,
+                &user.email,
+                &user.password,
+                &(user.role.as_int() as i32),
+                &user.email_verified,
+                &user.id,
+            ],
+        )?;
+        expect_row(updated, "user")
     }
 
-    pub  fn update_region(&mut self, region: &Region) -> bool {
-        match self.postgres.execute("UPDATE regions SET name=$1, transport_company=$2, frequency=$3, protocol=$4 WHERE id=$5",
-                              &[&region.name, &region.transport_company, &(region.frequency as i64), &region.protocol, &(region.id as i32)]) {
-            Err(e) => {
-                println!("Error updating region: {:?}", e);
-                false
-            }
-            _ => {
-                true
-            }
+    /// replaces the stored password hash, used to upgrade hashes of older schemes on login
+    pub fn set_password(&mut self, id: &Uuid, password: &str) -> Result<(), ServiceError> {
+        let updated = self
+            .postgres
+            .execute("UPDATE users SET password=$1 WHERE id=$2", &[&password, id])?;
+        expect_row(updated, "user")
+    }
+
+    pub fn update_station(&mut self, station: &Station) -> Result<(), ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE stations SET name=$1, lat=$2, lon=$3, region=$4, location_review=$5 WHERE id=$6",
+            &[
+                &station.name,
+                &station.lat,
+                &station.lon,
+                &(station.region as i32),
+                &station.location_review,
+                &station.id,
+            ],
+        )?;
+        expect_row(updated, "station")
+    }
+
+    pub fn update_region(&mut self, region: &Region) -> Result<(), ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE regions SET name=$1, transport_company=$2, frequency=$3, protocol=$4, bounds=$5 WHERE id=$6",
+            &[
+                &region.name,
+                &region.transport_company,
+                &(region.frequency as i64),
+                &region.protocol,
+                &region.bounds.as_ref().map(Json),
+                &(region.id as i32),
+            ],
+        )?;
+        expect_row(updated, "region")
+    }
+}
+
+/// turns "0 rows affected" of an UPDATE or DELETE into a not found error
+fn expect_row(affected: u64, entity: &'static str) -> Result<(), ServiceError> {
+    if affected == 0 {
+        Err(ServiceError::NotFound(entity))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_bounds(bounds: Option<Polygon>) -> Region {
+        Region {
+            id: 0,
+            name: String::from("test"),
+            transport_company: String::from("test"),
+            frequency: 170795000,
+            protocol: String::from("r09"),
+            bounds,
         }
     }
 
-    pub fn set_approved(&mut self, id: &Uuid, approved: bool) -> bool {
-        match self.postgres
-            .execute(
-                "UPDATE stations SET approved=$1 WHERE id=$2",
-                &[&approved, id],
-            ) {
-            Ok(data) => {
-                println!("Approve: {:?}", data);
-                true
-            }
-            Err(e) => {
-                println!("Approve: {:?}", e);
-                false
-            }
+    fn square() -> Polygon {
+        vec![[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]]
+    }
+
+    #[test]
+    fn region_without_bounds_contains_everything() {
+        let region = with_bounds(None);
+        assert!(region.contains(51.05, 13.74));
+        assert!(region.contains(-90.0, 180.0));
+    }
+
+    #[test]
+    fn region_contains_inside_and_not_outside() {
+        let region = with_bounds(Some(square()));
+        assert!(region.contains(5.0, 5.0));
+        assert!(region.contains(0.5, 9.5));
+        assert!(!region.contains(5.0, 15.0));
+        assert!(!region.contains(-5.0, 5.0));
+        assert!(!region.contains(11.0, 11.0));
+    }
+
+    #[test]
+    fn region_takes_points_as_lat_lon_and_bounds_as_lon_lat() {
+        // a wide strip, 20 degrees of longitude by 2 of latitude
+        let region = with_bounds(Some(vec![[0.0, 0.0], [20.0, 0.0], [20.0, 2.0], [0.0, 2.0]]));
+        assert!(region.contains(1.0, 15.0));
+        assert!(!region.contains(15.0, 1.0));
+    }
+
+    #[test]
+    fn region_contains_respects_concave_rings() {
+        // a U opening upwards, the notch between x 4..6 above y 4 is outside
+        let region = with_bounds(Some(vec![
+            [0.0, 0.0],
+            [10.0, 0.0],
+            [10.0, 10.0],
+            [6.0, 10.0],
+            [6.0, 4.0],
+            [4.0, 4.0],
+            [4.0, 10.0],
+            [0.0, 10.0],
+        ]));
+        assert!(region.contains(8.0, 2.0));
+        assert!(region.contains(8.0, 8.0));
+        assert!(!region.contains(8.0, 5.0));
+        assert!(region.contains(2.0, 5.0));
+    }
+
+    #[test]
+    fn region_contains_closed_and_unclosed_rings_alike() {
+        let open = with_bounds(Some(square()));
+        let mut ring = square();
+        ring.push(ring[0]);
+        let closed = with_bounds(Some(ring));
+
+        for (lat, lon) in [(5.0, 5.0), (9.9, 0.1), (15.0, 5.0), (5.0, -1.0), (0.0, 0.0), (10.0, 5.0)] {
+            assert_eq!(open.contains(lat, lon), closed.contains(lat, lon), "({}, {})", lat, lon);
         }
     }
 
-    pub fn set_token(&mut self, id: &Uuid, token: &String) -> bool {
-        self.postgres
-            .execute("UPDATE stations SET token=$1 WHERE id=$2", &[token, id])
-            .is_ok()
+    #[test]
+    fn region_contains_is_half_open_on_the_boundary() {
+        // ray casting puts the lower and left edges inside and the upper and right edges outside,
+        // so a point on the shared edge of two neighbouring regions lands in exactly one of them
+        let region = with_bounds(Some(square()));
+        assert!(region.contains(0.0, 5.0));
+        assert!(region.contains(5.0, 0.0));
+        assert!(!region.contains(10.0, 5.0));
+        assert!(!region.contains(5.0, 10.0));
+
+        assert!(region.contains(0.0, 0.0));
+        assert!(!region.contains(10.0, 10.0));
+        assert!(!region.contains(0.0, 10.0));
+        assert!(!region.contains(10.0, 0.0));
+
+        let right = with_bounds(Some(vec![[10.0, 0.0], [20.0, 0.0], [20.0, 10.0], [10.0, 10.0]]));
+        assert!(region.contains(5.0, 10.0) != right.contains(5.0, 10.0));
     }
 }
@@ -0,0 +1,183 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+use postgres::Row;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// where a station is in the approval process, only approved stations count as `approved`
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewState {
+    Pending,
+    Approved,
+    Rejected,
+    ChangesRequested,
+    Suspended,
+}
+
+impl ReviewState {
+    pub fn from(state: &str) -> ReviewState {
+        match state {
+            "approved" => ReviewState::Approved,
+            "rejected" => ReviewState::Rejected,
+            "changes_requested" => ReviewState::ChangesRequested,
+            "suspended" => ReviewState::Suspended,
+            _ => ReviewState::Pending,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewState::Pending => "pending",
+            ReviewState::Approved => "approved",
+            ReviewState::Rejected => "rejected",
+            ReviewState::ChangesRequested => "changes_requested",
+            ReviewState::Suspended => "suspended",
+        }
+    }
+
+    /// transitions an administrator may make, going back to pending is left to the owner
+    /// who resubmits the station
+    pub fn can_review_to(&self, next: ReviewState) -> bool {
+        use ReviewState::*;
+
+        matches!(
+            (self, next),
+            (Pending, Approved | Rejected | ChangesRequested)
+                | (ChangesRequested, Approved | Rejected)
+                | (Rejected, Approved)
+                | (Approved, Suspended | ChangesRequested)
+                | (Suspended, Approved | Rejected)
+        )
+    }
+
+    /// everything but approving has to be explained to the owner
+    pub fn needs_comment(&self) -> bool {
+        !matches!(self, ReviewState::Approved | ReviewState::Pending)
+    }
+}
+
+/// one transition in the history of a station
+#[derive(Serialize, Debug, Clone)]
+pub struct Review {
+    pub station: Uuid,
+    /// the administrator who made the decision, or the owner for resubmissions
+    pub reviewer: Option<Uuid>,
+    pub from_state: ReviewState,
+    pub to_state: ReviewState,
+    pub comment: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&Row> for Review {
+    fn from(row: &Row) -> Self {
+        Review {
+            station: row.get(0),
+            reviewer: row.get(1),
+            from_state: ReviewState::from(row.get::<usize, &str>(2)),
+            to_state: ReviewState::from(row.get::<usize, &str>(3)),
+            comment: row.get(4),
+            created_at: row.get(5),
+        }
+    }
+}
+
+impl DataBaseConnection {
+    /// moves the station into `to_state` and records the transition, fails with a conflict if
+    /// someone else changed the state since it was read
+    pub fn review_station(&mut self, review: &Review) -> Result<(), ServiceError> {
+        let mut transaction = self.atomic()?;
+
+        let updated = transaction.execute(
+            "UPDATE stations SET
+                review_state=$1,
+                approved=($1='approved'),
+                location_review=(location_review AND $1<>'approved')
+             WHERE id=$2 AND review_state=$3",
+            &[&review.to_state.as_str(), &review.station, &review.from_state.as_str()],
+        )?;
+
+        if updated == 0 {
+            return Err(ServiceError::Conflict(String::from("station was reviewed in the meantime")));
+        }
+
+        transaction.execute(
+            "INSERT INTO station_reviews (station, reviewer, from_state, to_state, comment, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &review.station,
+                &review.reviewer,
+                &review.from_state.as_str(),
+                &review.to_state.as_str(),
+                &review.comment,
+                &review.created_at,
+            ],
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn list_reviews(&mut self, station: &Uuid) -> Result<Vec<Review>, ServiceError> {
+        let data = self.postgres.query(
+            "SELECT station, reviewer, from_state, to_state, comment, created_at FROM station_reviews
+             WHERE station=$1 ORDER BY created_at, id",
+            &[station],
+        )?;
+
+        Ok(data.iter().map(Review::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReviewState::{self, *};
+
+    const STATES: [ReviewState; 5] = [Pending, Approved, Rejected, ChangesRequested, Suspended];
+
+    #[test]
+    fn allowed_review_transitions() {
+        let allowed = [
+            (Pending, Approved),
+            (Pending, Rejected),
+            (Pending, ChangesRequested),
+            (ChangesRequested, Approved),
+            (ChangesRequested, Rejected),
+            (Rejected, Approved),
+            (Approved, Suspended),
+            (Approved, ChangesRequested),
+            (Suspended, Approved),
+            (Suspended, Rejected),
+        ];
+
+        for from in STATES {
+            for to in STATES {
+                assert_eq!(from.can_review_to(to), allowed.contains(&(from, to)), "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_reviews_back_to_pending_or_to_itself() {
+        for state in STATES {
+            assert!(!state.can_review_to(Pending), "{:?} -> pending", state);
+            assert!(!state.can_review_to(state), "{:?} -> itself", state);
+        }
+    }
+
+    #[test]
+    fn states_survive_the_database_text() {
+        for state in STATES {
+            assert_eq!(ReviewState::from(state.as_str()), state);
+        }
+        assert_eq!(ReviewState::from("unknown"), Pending);
+    }
+
+    #[test]
+    fn only_approving_goes_without_comment() {
+        for state in STATES {
+            assert_eq!(state.needs_comment(), !matches!(state, Approved | Pending), "{:?}", state);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+use postgres::Row;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// server side login state, the token itself is only known to the client
+#[derive(Serialize, Debug, Clone)]
+pub struct Session {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+}
+
+impl From<&Row> for Session {
+    fn from(row: &Row) -> Self {
+        Session {
+            id: row.get(0),
+            owner: row.get(1),
+            created_at: row.get(2),
+            expires_at: row.get(3),
+            last_used: row.get(4),
+        }
+    }
+}
+
+impl DataBaseConnection {
+    pub fn create_session(&mut self, session: &Session, token_hash: &str) -> Result<(), ServiceError> {
+        self.postgres.execute(
+            "INSERT INTO sessions (id, token_hash, owner, created_at, expires_at, last_used) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &session.id,
+                &token_hash,
+                &session.owner,
+                &session.created_at,
+                &session.expires_at,
+                &session.last_used,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// finds the session of a token that has not expired yet and marks it as used
+    pub fn use_session(&mut self, token_hash: &str) -> Result<Session, ServiceError> {
+        let row = self
+            .postgres
+            .query_opt(
+                "UPDATE sessions SET last_used=now() WHERE token_hash=$1 AND expires_at > now()
+                 RETURNING id, owner, created_at, expires_at, last_used",
+                &[&token_hash],
+            )?
+            .ok_or(ServiceError::Unauthenticated)?;
+
+        Ok(Session::from(&row))
+    }
+
+    pub fn query_session(&mut self, id: &Uuid) -> Result<Session, ServiceError> {
+        let row = self
+            .postgres
+            .query_opt(
+                "SELECT id, owner, created_at, expires_at, last_used FROM sessions WHERE id=$1",
+                &[id],
+            )?
+            .ok_or(ServiceError::NotFound("session"))?;
+
+        Ok(Session::from(&row))
+    }
+
+    pub fn list_sessions(&mut self, owner: &Uuid) -> Result<Vec<Session>, ServiceError> {
+        let data = self.postgres.query(
+            "SELECT id, owner, created_at, expires_at, last_used FROM sessions
+             WHERE owner=$1 AND expires_at > now() ORDER BY last_used DESC",
+            &[owner],
+        )?;
+
+        Ok(data.iter().map(Session::from).collect())
+    }
+
+    pub fn delete_session(&mut self, id: &Uuid) -> Result<(), ServiceError> {
+        let deleted = self
+            .postgres
+            .execute("DELETE FROM sessions WHERE id=$1", &[id])?;
+        super::expect_row(deleted, "session")
+    }
+
+    /// logs the user out everywhere, returns the number of ended sessions
+    pub fn delete_user_sessions(&mut self, owner: &Uuid) -> Result<u64, ServiceError> {
+        Ok(self
+            .postgres
+            .execute("DELETE FROM sessions WHERE owner=$1", &[owner])?)
+    }
+
+    pub fn delete_expired_sessions(&mut self) -> Result<u64, ServiceError> {
+        Ok(self
+            .postgres
+            .execute("DELETE FROM sessions WHERE expires_at <= now()", &[])?)
+    }
+}
@@ -0,0 +1,86 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// the token a station currently uses plus the one it replaced, which stays valid until
+/// `previous_expires_at` so receivers can be redeployed without dropping out
+#[derive(Debug, Clone)]
+pub struct StationTokens {
+    pub token_hash: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub previous_hash: Option<String>,
+    pub previous_created_at: Option<DateTime<Utc>>,
+    pub previous_expires_at: Option<DateTime<Utc>>,
+}
+
+impl StationTokens {
+    /// the previous token if its grace period is not over yet
+    pub fn active_previous(&self) -> Option<(&str, DateTime<Utc>)> {
+        match (&self.previous_hash, self.previous_expires_at) {
+            (Some(hash), Some(expires_at)) if expires_at > Utc::now() => Some((hash, expires_at)),
+            _ => None,
+        }
+    }
+}
+
+impl DataBaseConnection {
+    pub fn query_station_tokens(&mut self, id: &Uuid) -> Result<StationTokens, ServiceError> {
+        let row = self
+            .postgres
+            .query_opt(
+                "SELECT token_hash, token_created_at, previous_token_hash, previous_token_created_at, previous_token_expires_at
+                 FROM stations WHERE id=$1",
+                &[id],
+            )?
+            .ok_or(ServiceError::NotFound("station"))?;
+
+        Ok(StationTokens {
+            token_hash: row.get(0),
+            created_at: row.get(1),
+            previous_hash: row.get(2),
+            previous_created_at: row.get(3),
+            previous_expires_at: row.get(4),
+        })
+    }
+
+    /// installs a new token, the current one becomes the previous token until `previous_expires_at`,
+    /// refused while the token replaced before is still in its grace period so it is never dropped
+    /// without notice
+    pub fn rotate_station_token(
+        &mut self,
+        id: &Uuid,
+        token_hash: &str,
+        previous_expires_at: DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE stations SET
+                previous_token_hash=token_hash,
+                previous_token_created_at=token_created_at,
+                previous_token_expires_at=$2,
+                token_hash=$1,
+                token_created_at=now()
+             WHERE id=$3 AND (previous_token_hash IS NULL OR previous_token_expires_at <= now())",
+            &[&token_hash, &previous_expires_at, id],
+        )?;
+
+        if updated == 0 {
+            if let Some((_, expires_at)) = self.query_station_tokens(id)?.active_previous() {
+                return Err(ServiceError::Conflict(format!(
+                    "the previous token is valid until {}, revoke it with station/revoke_previous_token first",
+                    expires_at.to_rfc3339()
+                )));
+            }
+        }
+        super::expect_row(updated, "station")
+    }
+
+    pub fn revoke_previous_station_token(&mut self, id: &Uuid) -> Result<(), ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE stations SET previous_token_hash=NULL, previous_token_created_at=NULL, previous_token_expires_at=NULL
+             WHERE id=$1",
+            &[id],
+        )?;
+        super::expect_row(updated, "station")
+    }
+}
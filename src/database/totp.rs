@@ -0,0 +1,119 @@
+use super::{DataBaseConnection, Role, ServiceError};
+
+use uuid::Uuid;
+
+/// stored two-factor state of an account, the secret is set from enrollment on but only
+/// asked for at login once `enabled` is
+#[derive(Debug, Clone)]
+pub struct TotpState {
+    pub secret: Option<String>,
+    pub enabled: bool,
+    /// newest time step a code was accepted for
+    pub last_step: Option<i64>,
+}
+
+impl DataBaseConnection {
+    pub fn query_totp(&mut self, user: &Uuid) -> Result<TotpState, ServiceError> {
+        let row = self
+            .postgres
+            .query_opt("SELECT totp_secret, totp_enabled, totp_last_step FROM users WHERE id=$1", &[user])?
+            .ok_or(ServiceError::NotFound("user"))?;
+
+        Ok(TotpState {
+            secret: row.get(0),
+            enabled: row.get(1),
+            last_step: row.get(2),
+        })
+    }
+
+    /// starts a new enrollment, accounts that already use a secret have to disable it first
+    pub fn set_pending_totp_secret(&mut self, user: &Uuid, secret: &str) -> Result<(), ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE users SET totp_secret=$1, totp_last_step=NULL WHERE id=$2 AND NOT totp_enabled",
+            &[&secret, user],
+        )?;
+
+        if updated == 0 {
+            return Err(ServiceError::Conflict(String::from("two-factor authentication is already enabled")));
+        }
+        Ok(())
+    }
+
+    /// remembers the step of an accepted code, false if it or a later one was used already, the
+    /// check happens in the update so two requests with the same code can not both pass
+    pub fn use_totp_step(&mut self, user: &Uuid, step: i64) -> Result<bool, ServiceError> {
+        let updated = self.postgres.execute(
+            "UPDATE users SET totp_last_step=$1 WHERE id=$2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+            &[&step, user],
+        )?;
+
+        Ok(updated == 1)
+    }
+
+    /// turns the pending secret on and replaces all recovery codes
+    pub fn enable_totp(&mut self, user: &Uuid, code_hashes: &[String]) -> Result<(), ServiceError> {
+        let mut transaction = self.atomic()?;
+
+        transaction.execute("UPDATE users SET totp_enabled=true WHERE id=$1", &[user])?;
+        transaction.execute("DELETE FROM totp_recovery_codes WHERE owner=$1", &[user])?;
+        for code_hash in code_hashes {
+            transaction.execute(
+                "INSERT INTO totp_recovery_codes (code_hash, owner) VALUES ($1, $2)",
+                &[code_hash, user],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn disable_totp(&mut self, user: &Uuid) -> Result<(), ServiceError> {
+        let mut transaction = self.atomic()?;
+
+        transaction.execute(
+            "UPDATE users SET totp_secret=NULL, totp_enabled=false, totp_last_step=NULL WHERE id=$1",
+            &[user],
+        )?;
+        transaction.execute("DELETE FROM totp_recovery_codes WHERE owner=$1", &[user])?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// removes the recovery code, false if the user has no such code
+    pub fn consume_recovery_code(&mut self, user: &Uuid, code_hash: &str) -> Result<bool, ServiceError> {
+        let deleted = self.postgres.execute(
+            "DELETE FROM totp_recovery_codes WHERE owner=$1 AND code_hash=$2",
+            &[user, &code_hash],
+        )?;
+
+        Ok(deleted == 1)
+    }
+
+    pub fn count_recovery_codes(&mut self, user: &Uuid) -> Result<i64, ServiceError> {
+        let row = self
+            .postgres
+            .query_one("SELECT COUNT(*) FROM totp_recovery_codes WHERE owner=$1", &[user])?;
+
+        Ok(row.get(0))
+    }
+
+    pub fn role_requires_totp(&mut self, role: &Role) -> Result<bool, ServiceError> {
+        let row = self.postgres.query_opt(
+            "SELECT require_totp FROM role_policies WHERE role=$1",
+            &[&(role.as_int() as i32)],
+        )?;
+
+        Ok(row.is_some_and(|row| row.get(0)))
+    }
+
+    pub fn set_role_requires_totp(&mut self, role: &Role, required: bool) -> Result<(), ServiceError> {
+        self.postgres.execute(
+            "INSERT INTO role_policies (role, require_totp) VALUES ($1, $2)
+                ON CONFLICT (role) DO UPDATE SET require_totp=EXCLUDED.require_totp",
+            &[&(role.as_int() as i32), &required],
+        )?;
+
+        Ok(())
+    }
+}
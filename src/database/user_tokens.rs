@@ -0,0 +1,77 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// what a one-time token mailed to a user is good for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+    /// handed out after the password when the account has two-factor authentication
+    LoginChallenge,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::VerifyEmail => "verify_email",
+            TokenPurpose::ResetPassword => "reset_password",
+            TokenPurpose::LoginChallenge => "login_challenge",
+        }
+    }
+}
+
+impl DataBaseConnection {
+    /// stores the hash of a new token, older tokens of the user for the same purpose stop working
+    pub fn create_user_token(
+        &mut self,
+        owner: &Uuid,
+        purpose: TokenPurpose,
+        token_hash: &str,
+        expires_at: &DateTime<Utc>,
+    ) -> Result<(), ServiceError> {
+        let mut transaction = self.atomic()?;
+
+        transaction.execute(
+            "DELETE FROM user_tokens WHERE (owner=$1 AND purpose=$2) OR expires_at < now()",
+            &[owner, &purpose.as_str()],
+        )?;
+        transaction.execute(
+            "INSERT INTO user_tokens (token_hash, owner, purpose, expires_at) VALUES ($1, $2, $3, $4)",
+            &[&token_hash, owner, &purpose.as_str(), expires_at],
+        )?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// removes the token and returns its owner, each token works exactly once
+    pub fn consume_user_token(&mut self, purpose: TokenPurpose, token_hash: &str) -> Result<Uuid, ServiceError> {
+        let row = self.postgres.query_opt(
+            "DELETE FROM user_tokens WHERE token_hash=$1 AND purpose=$2 AND expires_at > now() RETURNING owner",
+            &[&token_hash, &purpose.as_str()],
+        )?;
+
+        row.map(|row| row.get(0))
+            .ok_or_else(|| ServiceError::Validation(String::from("invalid or expired token")))
+    }
+
+    /// owner of a valid token without using it up
+    pub fn peek_user_token(&mut self, purpose: TokenPurpose, token_hash: &str) -> Result<Uuid, ServiceError> {
+        let row = self.postgres.query_opt(
+            "SELECT owner FROM user_tokens WHERE token_hash=$1 AND purpose=$2 AND expires_at > now()",
+            &[&token_hash, &purpose.as_str()],
+        )?;
+
+        row.map(|row| row.get(0))
+            .ok_or_else(|| ServiceError::Validation(String::from("invalid or expired token")))
+    }
+
+    pub fn set_email_verified(&mut self, id: &Uuid, verified: bool) -> Result<(), ServiceError> {
+        self.postgres
+            .execute("UPDATE users SET email_verified=$1 WHERE id=$2", &[&verified, id])?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use super::{token, AuditEntry, AuditFilter, DataBaseConnection, ListOptions, Page, Permission, Role, ServiceError, UserConnection};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ListAuditRequest {
+    #[serde(flatten)]
+    pub filter: AuditFilter,
+    #[serde(flatten)]
+    pub options: ListOptions,
+}
+
+/// the entity an operation changes
+#[derive(Debug, Clone, Copy)]
+enum AuditTarget {
+    Station(Uuid),
+    Region(u32),
+    User(Uuid),
+    Session(Uuid),
+    Role(u32),
+}
+
+impl AuditTarget {
+    fn kind(&self) -> &'static str {
+        match self {
+            AuditTarget::Station(_) => "station",
+            AuditTarget::Region(_) => "region",
+            AuditTarget::User(_) => "user",
+            AuditTarget::Session(_) => "session",
+            AuditTarget::Role(_) => "role",
+        }
+    }
+
+    fn id(&self) -> String {
+        match self {
+            AuditTarget::Station(id) | AuditTarget::User(id) | AuditTarget::Session(id) => id.to_string(),
+            AuditTarget::Region(id) | AuditTarget::Role(id) => id.to_string(),
+        }
+    }
+}
+
+fn uuid_field(value: Option<&Value>) -> Option<Uuid> {
+    value?.get("id")?.as_str()?.parse().ok()
+}
+
+fn region_field(value: Option<&Value>) -> Option<u32> {
+    value?.get("id")?.as_u64().map(|id| id as u32)
+}
+
+fn role_field(value: Option<&Value>) -> Option<u32> {
+    let role = serde_json::from_value::<Role>(value?.get("role")?.clone()).ok()?;
+    Some(role.as_int())
+}
+
+/// whether the operation changes data and which entity it targets, creations only learn their
+/// target from the response
+fn audited_target(connection: &UserConnection, operation: &str, body: Option<&Value>) -> Option<Option<AuditTarget>> {
+    let target = match operation {
+        "user/register" | "user/verify_email" | "user/request_password_reset" | "user/reset_password"
+        | "user/invite" | "station/create" | "region/create" => None,
+        // the session only exists afterwards
+        "user/login" | "user/login_totp" | "user/resume" => None,
+        "user/logout" => connection.session.map(AuditTarget::Session),
+        "user/resend_verification" => connection.user.as_ref().map(|user| AuditTarget::User(user.id)),
+        "user/revoke_session" => uuid_field(body).map(AuditTarget::Session),
+        "user/delete" | "user/modify" | "user/unlock" => uuid_field(body).map(AuditTarget::User),
+        "user/totp/enroll" | "user/totp/confirm" | "user/totp/disable" => uuid_field(body)
+            .or_else(|| connection.user.as_ref().map(|user| user.id))
+            .map(AuditTarget::User),
+        "role/require_totp" => role_field(body).map(AuditTarget::Role),
+        // the config only changes something when it rotates the token
+        "station/config" if !body.is_some_and(|body| body.get("rotate") == Some(&Value::Bool(true))) => return None,
+        "station/delete" | "station/modify" | "station/approve" | "station/review" | "station/generate_token"
+        | "station/revoke_previous_token" | "station/config" => uuid_field(body).map(AuditTarget::Station),
+        "region/delete" | "region/modify" => region_field(body).map(AuditTarget::Region),
+        // heartbeats are telemetry, `station/heartbeats` keeps them
+        _ => return None,
+    };
+
+    Some(target)
+}
+
+fn created_target(operation: &str, data: &Value) -> Option<AuditTarget> {
+    match operation {
+        "user/register" | "user/verify_email" | "user/reset_password" => uuid_field(Some(data)).map(AuditTarget::User),
+        "station/create" => uuid_field(Some(data)).map(AuditTarget::Station),
+        "region/create" => region_field(Some(data)).map(AuditTarget::Region),
+        _ => None,
+    }
+}
+
+/// session a login started, `user/login` only starts one without two-factor authentication
+fn session_target(connection: &UserConnection, operation: &str) -> Option<AuditTarget> {
+    match operation {
+        "user/login" | "user/login_totp" | "user/resume" => connection.session.map(AuditTarget::Session),
+        _ => None,
+    }
+}
+
+/// state of the target as it is stored, `None` if it does not exist (anymore)
+fn snapshot(database: &mut DataBaseConnection, target: AuditTarget) -> Option<Value> {
+    match target {
+        AuditTarget::Station(id) => {
+            let mut station = serde_json::to_value(database.query_station(&id).ok()?).ok()?;
+            let tokens = database.query_station_tokens(&id).ok()?;
+
+            let fields = station.as_object_mut()?;
+            fields.remove("online");
+            fields.remove("last_seen");
+            fields.insert(String::from("token_created_at"), json!(tokens.created_at));
+            fields.insert(String::from("previous_token_expires_at"), json!(tokens.previous_expires_at));
+            Some(station)
+        }
+        AuditTarget::Region(id) => serde_json::to_value(database.query_region(&id).ok()?).ok(),
+        AuditTarget::User(id) => {
+            let user = database.query_user_by_id(&id).ok()?;
+            // only used to see that the password changed, the value itself is never written
+            Some(json!({
+                "id": user.id,
+                "name": user.name,
+                "email": user.email,
+                "role": user.role,
+                "email_verified": user.email_verified,
+                "totp_enabled": user.totp_enabled,
+                "password": token::hash(&user.password),
+            }))
+        }
+        AuditTarget::Session(_) => None,
+        AuditTarget::Role(id) => {
+            let role = Role::from(id);
+            Some(json!({
+                "role": role,
+                "require_totp": database.role_requires_totp(&role).ok()?,
+            }))
+        }
+    }
+}
+
+/// fields that differ between the two snapshots, secrets are only marked as changed
+fn diff(before: &Option<Value>, after: &Option<Value>) -> Option<Value> {
+    let empty = Map::new();
+    let before_fields = before.as_ref().and_then(Value::as_object).unwrap_or(&empty);
+    let after_fields = after.as_ref().and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for key in before_fields.keys().chain(after_fields.keys()) {
+        let old = before_fields.get(key).unwrap_or(&Value::Null);
+        let new = after_fields.get(key).unwrap_or(&Value::Null);
+        if old == new || changes.contains_key(key) {
+            continue;
+        }
+
+        let change = if key == "password" {
+            json!({ "changed": true })
+        } else {
+            json!({ "before": old, "after": new })
+        };
+        changes.insert(key.clone(), change);
+    }
+
+    if changes.is_empty() {
+        None
+    } else {
+        Some(Value::Object(changes))
+    }
+}
+
+/// what is known about a mutation before it runs
+pub struct AuditRecord {
+    operation: String,
+    actor: Option<Uuid>,
+    target: Option<AuditTarget>,
+    before: Option<Value>,
+}
+
+impl AuditRecord {
+    /// whether the operation changes anything and gets an entry
+    pub fn audited(connection: &UserConnection, operation: &str, body: Option<&Value>) -> bool {
+        audited_target(connection, operation, body).is_some()
+    }
+
+    pub fn begin(connection: &UserConnection, operation: &str, body: Option<&Value>) -> AuditRecord {
+        let target = audited_target(connection, operation, body).flatten();
+        let before = match (target, connection.database.get()) {
+            (Some(target), Ok(mut database)) => snapshot(&mut database, target),
+            _ => None,
+        };
+
+        AuditRecord {
+            operation: operation.to_string(),
+            actor: connection.user.as_ref().map(|user| user.id),
+            target,
+            before,
+        }
+    }
+
+    /// writes the entry after the operation succeeded, in the transaction of the request, if that
+    /// fails the request is rolled back so no change is missing from the log
+    pub fn finish(self, connection: &UserConnection, data: &Value) -> Result<(), ServiceError> {
+        let target = self
+            .target
+            .or_else(|| created_target(&self.operation, data))
+            .or_else(|| session_target(connection, &self.operation));
+
+        let result = connection.database.get().map_err(ServiceError::from).and_then(|mut database| {
+            let after = target.and_then(|target| snapshot(&mut database, target));
+
+            database.record_audit(&AuditEntry {
+                id: 0,
+                created_at: Utc::now(),
+                actor: self.actor.or_else(|| connection.user.as_ref().map(|user| user.id)),
+                operation: self.operation.clone(),
+                target_type: target.map(|target| target.kind().to_string()),
+                target_id: target.map(|target| target.id()),
+                diff: diff(&self.before, &after),
+                address: connection.address.map(|address| address.ip().to_string()),
+            })
+        });
+
+        result.map_err(|e| {
+            println!("{} is rolled back, it could not be written to the audit log {:?}", self.operation, e);
+            ServiceError::Internal
+        })
+    }
+}
+
+pub fn list_audit(connection: &mut UserConnection, request: ListAuditRequest) -> Result<Page<AuditEntry>, ServiceError> {
+    connection.require(Permission::ViewAuditLog)?;
+
+    connection.database.get()?.list_audit(&request.filter, &request.options)
+}
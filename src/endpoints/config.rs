@@ -0,0 +1,134 @@
+use super::station::{grace_period_end, match_token, TokenMatch};
+use super::{token, Permission, ServiceError, UserConnection};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    Nixos,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StationConfigRequest {
+    pub id: Uuid,
+    #[serde(default)]
+    pub format: ConfigFormat,
+    /// the server only knows the hash of the station token, so either the current token is
+    /// given or `rotate` asks for a new one
+    pub token: Option<String>,
+    /// replaces the token like `station/generate_token`, the old one stays valid for the grace period
+    #[serde(default)]
+    pub rotate: bool,
+}
+
+/// everything the receiver software needs to start collecting telegrams
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ReceiverConfig {
+    pub station: Uuid,
+    pub name: String,
+    pub token: String,
+    pub region: u32,
+    pub frequency: u64,
+    pub protocol: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// data sinks the decoded telegrams are sent to
+    pub telegram_endpoints: Vec<String>,
+    pub heartbeat_url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum StationConfigResponse {
+    Json(ReceiverConfig),
+    Nixos(String),
+}
+
+fn nix_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${"))
+}
+
+/// module options of the dump-dvb receiver flake
+fn render_nixos(config: &ReceiverConfig) -> String {
+    let endpoints: Vec<String> = config.telegram_endpoints.iter().map(|endpoint| nix_string(endpoint)).collect();
+
+    let mut snippet = String::new();
+    snippet.push_str("{\n");
+    snippet.push_str("  dump-dvb.radioReceiver = {\n");
+    snippet.push_str("    enable = true;\n");
+    snippet.push_str(&format!("    frequency = {};\n", config.frequency));
+    snippet.push_str("  };\n");
+    snippet.push_str("  dump-dvb.telegramDecoder = {\n");
+    snippet.push_str("    enable = true;\n");
+    snippet.push_str(&format!("    station = {};\n", nix_string(&config.station.to_string())));
+    snippet.push_str(&format!("    name = {};\n", nix_string(&config.name)));
+    snippet.push_str(&format!("    token = {};\n", nix_string(&config.token)));
+    snippet.push_str(&format!("    region = {};\n", config.region));
+    snippet.push_str(&format!("    protocol = {};\n", nix_string(&config.protocol)));
+    snippet.push_str(&format!("    server = [ {} ];\n", endpoints.join(" ")));
+    if let Some(heartbeat_url) = &config.heartbeat_url {
+        snippet.push_str(&format!("    heartbeatUrl = {};\n", nix_string(heartbeat_url)));
+    }
+    snippet.push_str("  };\n");
+    snippet.push_str("}\n");
+
+    snippet
+}
+
+pub fn station_config(connection: &mut UserConnection, request: StationConfigRequest) -> Result<StationConfigResponse, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
+
+    let station = database.query_station(&request.id)?;
+    let region = database.query_region(&station.region)?;
+
+    let token = match (request.token, request.rotate) {
+        (Some(_), true) => {
+            return Err(ServiceError::Validation(String::from("either token or rotate, not both")));
+        }
+        (Some(token), false) => {
+            let tokens = database.query_station_tokens(&request.id)?;
+            if !matches!(match_token(&tokens, &token), Some(TokenMatch::Current)) {
+                return Err(ServiceError::Validation(String::from("token does not belong to this station")));
+            }
+            token
+        }
+        (None, true) => {
+            let token = token::generate(32);
+            let previous_expires_at = grace_period_end(connection.token_grace_period)?;
+            database.rotate_station_token(&request.id, &token::hash(&token), previous_expires_at)?;
+            token
+        }
+        (None, false) => {
+            return Err(ServiceError::Validation(String::from(
+                "the current token is required, or rotate to generate a new one",
+            )));
+        }
+    };
+
+    let config = ReceiverConfig {
+        station: station.id,
+        name: station.name,
+        token,
+        region: region.id,
+        frequency: region.frequency,
+        protocol: region.protocol,
+        lat: station.lat,
+        lon: station.lon,
+        telegram_endpoints: connection.telegram_endpoints.clone(),
+        heartbeat_url: connection
+            .public_url
+            .as_ref()
+            .map(|url| format!("{}/stations/{}/heartbeat", url.trim_end_matches('/'), station.id)),
+    };
+
+    Ok(match request.format {
+        ConfigFormat::Json => StationConfigResponse::Json(config),
+        ConfigFormat::Nixos => StationConfigResponse::Nixos(render_nixos(&config)),
+    })
+}
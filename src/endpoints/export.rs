@@ -0,0 +1,67 @@
+use super::station::mark_online;
+use super::{Polygon, ServiceError, UserConnection};
+
+use serde_json::{json, Value};
+
+/// geojson wants closed rings where the last point repeats the first one
+fn closed_ring(bounds: &Polygon) -> Polygon {
+    let mut ring = bounds.clone();
+    if ring.first() != ring.last() {
+        ring.push(ring[0]);
+    }
+    ring
+}
+
+/// FeatureCollection of the approved stations as points and the bounds of the regions as
+/// polygons, meant to be public so owners and tokens are left out
+pub fn export_geojson(connection: &mut UserConnection) -> Result<Value, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    let mut stations = database.approved_stations()?;
+    mark_online(connection, &mut stations);
+
+    let regions = database.all_regions()?;
+
+    let station_features = stations.iter().map(|station| {
+        json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": [station.lon, station.lat],
+            },
+            "properties": {
+                "kind": "station",
+                "id": station.id,
+                "name": station.name,
+                "region": station.region,
+                "online": station.online,
+                "last_seen": station.last_seen,
+            },
+        })
+    });
+
+    let region_features = regions.iter().filter_map(|region| {
+        let bounds = region.bounds.as_ref()?;
+
+        Some(json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [closed_ring(bounds)],
+            },
+            "properties": {
+                "kind": "region",
+                "id": region.id,
+                "name": region.name,
+                "transport_company": region.transport_company,
+                "frequency": region.frequency,
+                "protocol": region.protocol,
+            },
+        }))
+    });
+
+    Ok(json!({
+        "type": "FeatureCollection",
+        "features": region_features.chain(station_features).collect::<Vec<Value>>(),
+    }))
+}
@@ -0,0 +1,60 @@
+use super::{token, validate_email, Mail, Permission, ServiceError, UserConnection};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct InviteRequest {
+    /// the invite is mailed there and only works for registering with this address
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct InviteResponse {
+    /// only handed out for invites without address, the others are only in the mail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invite: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// creates a one-time invite for `user/register`
+pub fn create_invite(connection: &mut UserConnection, request: InviteRequest) -> Result<InviteResponse, ServiceError> {
+    connection.require(Permission::ManageUsers)?;
+    if let Some(email) = &request.email {
+        validate_email(email)?;
+    }
+
+    let mut database = connection.database.get()?;
+    let invite = token::generate(32);
+    let expires_at = Utc::now() + Duration::days(connection.invite_lifetime);
+    let inviter = connection.user.clone().unwrap();
+
+    database.create_invite(&token::hash(&invite), &inviter.id, request.email.as_deref(), &expires_at)?;
+
+    let email = match request.email {
+        Some(email) => email,
+        None => {
+            return Ok(InviteResponse {
+                invite: Some(invite),
+                expires_at,
+            })
+        }
+    };
+
+    connection.mailer.send(&Mail {
+        to: email,
+        subject: String::from("You are invited"),
+        body: format!(
+            "Hello,\n\n{} invited you to create an account. Send this invite together with your name, this \
+             address and a password to user/register:\n\n{}\n\nThe invite is valid until {}.\n",
+            inviter.name,
+            invite,
+            expires_at.to_rfc3339()
+        ),
+    })?;
+
+    Ok(InviteResponse {
+        invite: None,
+        expires_at,
+    })
+}
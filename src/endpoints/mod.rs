@@ -1,32 +1,113 @@
+mod audit;
+mod config;
+mod export;
+mod invite;
+mod password_reset;
 mod region;
+mod review;
+mod session;
 mod station;
+mod subscription;
+mod two_factor;
 mod user;
+mod verification;
 
-pub use super::{Region, Role, Station, User, UserConnection};
+use super::mail::Mail;
+use super::structs::RegistrationMode;
+use super::token;
+use super::totp;
+use super::throttle;
+use super::password::Verification;
+pub use super::{text_or_value, AuditEntry, AuditFilter, DataBaseConnection, ErrorCode, Heartbeat, ListOptions, Page, Permission, Polygon, Region, Review, ReviewState, Role, ServiceError, Session, Station, StationFilter, StationTokens, TokenPurpose, User, UserConnection};
+pub use super::events::{Change, Topic};
 
 pub use station::{
-    approve_station, create_station, delete_station, generate_token, list_stations, modify_station,
-    ApproveStation, CreateStationRequest, ListStationsRequest, ModifyStation,
+    create_station, delete_station, generate_token, heartbeat, list_heartbeats, list_stations,
+    modify_station, revoke_previous_token, station_tokens, verify_token, CreateStationRequest,
+    GenerateTokenRequest, HeartbeatRequest, ListStationsRequest, ModifyStation, VerifyTokenRequest,
 };
 pub use user::{
-    create_user, delete_user, get_session, login, modify_user, list_users,
+    create_user, delete_user, get_session, login, modify_user, list_users, unlock_login, validate_email, UnlockLoginRequest,
     LoginRequest, ModifyUserRequest,
     RegisterUserRequest, UuidRequest,
 };
 
+pub use audit::{list_audit, AuditRecord, ListAuditRequest};
+pub use config::{station_config, StationConfigRequest};
+pub use export::export_geojson;
+pub use invite::{create_invite, InviteRequest};
+
+pub use review::{approve_station, list_reviews, review_station, ApproveStation, ReviewStationRequest};
+
+pub use password_reset::{request_password_reset, reset_password, RequestPasswordReset, ResetPasswordRequest};
+pub use verification::{resend_verification, verify_email, TokenRequest};
+pub use subscription::{list_subscriptions, subscribe, unsubscribe};
+pub use two_factor::{
+    check_totp_policy, confirm_totp, disable_totp, enroll_totp, list_role_policies, login_totp, set_role_policy,
+    DisableTotpRequest, RolePolicyRequest, TotpCodeRequest, TotpLoginRequest,
+};
+
+pub use session::{list_sessions, logout, resume, revoke_session, ListSessionsRequest, ResumeRequest};
+
 pub use region::{
     create_region, delete_region, list_regions, modify_region, ModifyRegionRequest, RegionRequest,
 };
 
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct IdentifierRequest {
     pub id: u32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IdentifierResponse {
+    pub id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UuidResponse {
+    pub id: Uuid,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+/// envelope every answer is wrapped in, `id` is whatever the client sent along with the
+/// request so pipelined requests can be matched to their responses
+#[derive(Serialize, Debug)]
 pub struct ServiceResponse {
-    success: bool,
-    message: Option<String>
+    pub operation: String,
+    pub id: Option<serde_json::Value>,
+    pub success: bool,
+    pub data: serde_json::Value,
+    pub error: Option<ErrorResponse>,
+}
+
+impl ServiceResponse {
+    pub fn new(operation: String, id: Option<serde_json::Value>, result: Result<serde_json::Value, ServiceError>) -> ServiceResponse {
+        match result {
+            Ok(data) => ServiceResponse {
+                operation,
+                id,
+                success: true,
+                data,
+                error: None,
+            },
+            Err(e) => ServiceResponse {
+                operation,
+                id,
+                success: false,
+                data: serde_json::Value::Null,
+                error: Some(ErrorResponse {
+                    code: e.code(),
+                    message: e.to_string(),
+                }),
+            },
+        }
+    }
 }
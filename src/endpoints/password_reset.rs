@@ -0,0 +1,85 @@
+use super::throttle::account_key;
+use super::{token, Change, Mail, ServiceError, TokenPurpose, UserConnection, UuidResponse};
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RequestPasswordReset {
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+/// mails a reset code to every account of the address, the answer is the same whether there
+/// is such an account or not
+pub fn request_password_reset(connection: &mut UserConnection, request: RequestPasswordReset) -> Result<(), ServiceError> {
+    let mut database = connection.database.get()?;
+    let address = connection.address.map(|address| address.ip());
+    connection.throttle.reset_requested(&mut database, &request.email, address)?;
+
+    let expires_at = Utc::now() + Duration::minutes(connection.password_reset_lifetime);
+
+    let mut mails = Vec::new();
+    for user in database.query_users_by_email(&request.email)? {
+        let token = token::generate(32);
+        database.create_user_token(&user.id, TokenPurpose::ResetPassword, &token::hash(&token), &expires_at)?;
+
+        mails.push(Mail {
+            to: user.email,
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Hello {},\n\nsomebody asked to reset the password of your account. If that was you, send this \
+                 code together with your new password to user/reset_password:\n\n{}\n\nThe code is valid until {}. \
+                 If you did not ask for this you can ignore this mail.\n",
+                user.name,
+                token,
+                expires_at.to_rfc3339()
+            ),
+        });
+    }
+
+    // sending takes long enough to tell existing accounts apart, so it happens after the answer on
+    // the bounded blocking pool of the runtime
+    let mailer = connection.mailer.clone();
+    task::spawn_blocking(move || {
+        for mail in mails {
+            if let Err(e) = mailer.send(&mail) {
+                println!("could not send password reset mail {:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// sets the new password and ends every session of the account
+pub fn reset_password(connection: &mut UserConnection, request: ResetPasswordRequest) -> Result<UuidResponse, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    let owner = database.consume_user_token(TokenPurpose::ResetPassword, &token::hash(&request.token))?;
+    let user = database.query_user_by_id(&owner)?;
+
+    let password_hash = connection.passwords.hash(&request.password)?;
+    database.set_password(&owner, &password_hash)?;
+
+    let ended = database.delete_user_sessions(&owner)?;
+    println!("password of {} was reset, ended {} sessions", user.name, ended);
+
+    database.clear_login_failures(&account_key(&user.name))?;
+    // the code came through the mail so the address works
+    if !user.email_verified {
+        database.set_email_verified(&owner, true)?;
+    }
+
+    connection.publish(Change::User {
+        id: owner,
+        action: "password_reset",
+    });
+    Ok(UuidResponse { id: owner })
+}
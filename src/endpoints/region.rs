@@ -1,5 +1,5 @@
-use super::IdentifierRequest;
-use super::{Region, ServiceResponse, UserConnection};
+use super::{IdentifierRequest, IdentifierResponse};
+use super::{Change, ListOptions, Page, Permission, Polygon, Region, ServiceError, UserConnection};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -8,6 +8,7 @@ pub struct RegionRequest {
     pub transport_company: String,
     pub frequency: u64,
     pub protocol: String,
+    pub bounds: Option<Polygon>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -17,63 +18,60 @@ pub struct ModifyRegionRequest {
     pub transport_company: Option<String>,
     pub frequency: Option<u64>,
     pub protocol: Option<String>,
+    /// an empty list removes the bounds
+    pub bounds: Option<Polygon>,
 }
 
-fn admin(connection: &mut UserConnection) -> bool {
-    connection.user.as_ref().unwrap().is_admin()
-}
+fn validate_bounds(bounds: &Polygon) -> Result<(), ServiceError> {
+    if bounds.len() < 3 {
+        return Err(ServiceError::Validation(String::from("bounds need at least three points")));
+    }
 
-fn write_error(connection: &mut UserConnection, message: Option<String>) {
-    let serialized = serde_json::to_string(&ServiceResponse { success: false, message: message}).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
+    if bounds
+        .iter()
+        .any(|[lon, lat]| !(-180.0..=180.0).contains(lon) || !(-90.0..=90.0).contains(lat))
+    {
+        return Err(ServiceError::Validation(String::from("bounds contain invalid coordinates")));
+    }
+
+    Ok(())
 }
 
-pub fn create_region(connection: &mut UserConnection, request: RegionRequest) {
-    println!("message: {}", admin(connection));
-    if !admin(connection) {
-        write_error(connection, Some("you are not administrator".to_string()));
-        return;
+pub fn create_region(connection: &mut UserConnection, request: RegionRequest) -> Result<IdentifierResponse, ServiceError> {
+    connection.require(Permission::ManageRegions)?;
+
+    if let Some(bounds) = &request.bounds {
+        validate_bounds(bounds)?;
     }
 
-    let result = connection.database.lock().unwrap().create_region(&Region {
+    let id = connection.database.get()?.create_region(&Region {
         id: 0,
         name: request.name,
         transport_company: request.transport_company,
         frequency: request.frequency,
         protocol: request.protocol,
-    });
-
+        bounds: request.bounds,
+    })?;
 
-    let serialized = serde_json::to_string(&ServiceResponse { success: result, message: None }).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
+    Ok(IdentifierResponse { id })
 }
 
-pub fn modify_region(connection: &mut UserConnection, request: ModifyRegionRequest) {
-    if !admin(connection) {
-        write_error(connection, Some("you are not administrator".to_string()));
-        return;
-    }
+pub fn modify_region(connection: &mut UserConnection, request: ModifyRegionRequest) -> Result<(), ServiceError> {
+    connection.require(Permission::ManageRegions)?;
 
-    let result_region = connection
-        .database
-        .lock()
-        .unwrap()
-        .query_region(&request.id);
+    let mut database = connection.database.get()?;
+    let region = database.query_region(&request.id)?;
 
-    if result_region.is_none() {
-        write_error(connection, Some("this region does not exists".to_string()));
-        return;
-    }
+    let bounds = match request.bounds {
+        Some(bounds) if bounds.is_empty() => None,
+        Some(bounds) => {
+            validate_bounds(&bounds)?;
+            Some(bounds)
+        }
+        None => region.bounds,
+    };
 
-    let region = result_region.unwrap();
-
-    let result = connection.database.lock().unwrap().update_region(&Region {
+    database.update_region(&Region {
         id: request.id,
         name: request.name.unwrap_or(region.name),
         transport_company: request
@@ -81,39 +79,28 @@ pub fn modify_region(connection: &mut UserConnection, request: ModifyRegionReque
             .unwrap_or(region.transport_company),
         frequency: request.frequency.unwrap_or(region.frequency),
         protocol: request.protocol.unwrap_or(region.protocol),
+        bounds,
+    })?;
+
+    connection.publish(Change::Region {
+        id: request.id,
+        action: "modified",
     });
-    let serialized = serde_json::to_string(&ServiceResponse { success: result, message: None }).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
+    Ok(())
 }
 
-pub fn delete_region(connection: &mut UserConnection, request: IdentifierRequest) {
-    if !admin(connection) {
-        write_error(connection, Some("you are not administrator".to_string()));
-        return;
-    }
+pub fn delete_region(connection: &mut UserConnection, request: IdentifierRequest) -> Result<(), ServiceError> {
+    connection.require(Permission::ManageRegions)?;
 
-    let result = connection
-        .database
-        .lock()
-        .unwrap()
-        .delete_region(&request.id);
-
-    let serialized = serde_json::to_string(&ServiceResponse { success: result, message: None }).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
-}
+    connection.database.get()?.delete_region(&request.id)?;
 
-pub fn list_regions(connection: &mut UserConnection) {
-    let data = connection.database.lock().unwrap().list_regions();
+    connection.publish(Change::Region {
+        id: request.id,
+        action: "deleted",
+    });
+    Ok(())
+}
 
-    let serialized = serde_json::to_string(&data).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
+pub fn list_regions(connection: &mut UserConnection, options: ListOptions) -> Result<Page<Region>, ServiceError> {
+    connection.database.get()?.list_regions(&options)
 }
@@ -0,0 +1,99 @@
+use super::{Change, Permission, Review, ReviewState, ServiceError, Station, UserConnection, UuidRequest};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ReviewStationRequest {
+    pub id: Uuid,
+    pub state: ReviewState,
+    /// required for everything but approving
+    pub comment: Option<String>,
+}
+
+/// shorthand of `station/review`, declining suspends an approved station and rejects any other, clients
+/// that predate the review states decline without a comment
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ApproveStation {
+    pub id: Uuid,
+    pub approved: bool,
+    pub comment: Option<String>,
+}
+
+pub fn review_station(connection: &mut UserConnection, request: ReviewStationRequest) -> Result<(), ServiceError> {
+    connection.require(Permission::ApproveStations)?;
+    let user = connection.user.as_ref().unwrap();
+
+    let mut database = connection.database.get()?;
+    let station = database.query_station(&request.id)?;
+
+    if !station.review_state.can_review_to(request.state) {
+        return Err(ServiceError::Validation(format!(
+            "a {} station can not be {}",
+            station.review_state.as_str(),
+            request.state.as_str()
+        )));
+    }
+
+    let comment = request.comment.filter(|comment| !comment.trim().is_empty());
+    if request.state.needs_comment() && comment.is_none() {
+        return Err(ServiceError::Validation(format!(
+            "a comment is required for {}",
+            request.state.as_str()
+        )));
+    }
+
+    database.review_station(&Review {
+        station: station.id,
+        reviewer: Some(user.id),
+        from_state: station.review_state,
+        to_state: request.state,
+        comment,
+        created_at: Utc::now(),
+    })?;
+
+    connection.publish(Change::station(
+        &Station {
+            review_state: request.state,
+            ..station
+        },
+        "reviewed",
+    ));
+    Ok(())
+}
+
+pub fn approve_station(connection: &mut UserConnection, request: ApproveStation) -> Result<(), ServiceError> {
+    connection.require(Permission::ApproveStations)?;
+
+    let state = if request.approved {
+        ReviewState::Approved
+    } else if connection.database.get()?.query_station(&request.id)?.review_state == ReviewState::Approved {
+        ReviewState::Suspended
+    } else {
+        ReviewState::Rejected
+    };
+
+    let comment = request
+        .comment
+        .filter(|comment| !comment.trim().is_empty())
+        .or_else(|| state.needs_comment().then(|| String::from("declined via station/approve")));
+
+    review_station(
+        connection,
+        ReviewStationRequest {
+            id: request.id,
+            state,
+            comment,
+        },
+    )
+}
+
+/// the full review history of a station, oldest first
+pub fn list_reviews(connection: &mut UserConnection, request: UuidRequest) -> Result<Vec<Review>, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ApproveStations)?;
+
+    database.list_reviews(&request.id)
+}
@@ -0,0 +1,57 @@
+use super::{Permission, ServiceError, Session, UserConnection, UuidRequest};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ResumeRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ListSessionsRequest {
+    /// only administrators may look at the sessions of other users
+    pub user: Option<Uuid>,
+}
+
+pub fn resume(connection: &mut UserConnection, request: ResumeRequest) -> Result<Session, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    let session = connection.sessions.resume(&mut database, &request.token)?;
+    connection.user = Some(database.query_user_by_id(&session.owner)?);
+    connection.session = Some(session.id);
+
+    Ok(session)
+}
+
+pub fn logout(connection: &mut UserConnection) -> Result<(), ServiceError> {
+    if let Some(session) = connection.session.take() {
+        connection.database.get()?.delete_session(&session)?;
+    }
+
+    connection.user = None;
+    connection.events.disconnect(&connection.id);
+    Ok(())
+}
+
+pub fn list_sessions(connection: &mut UserConnection, request: ListSessionsRequest) -> Result<Vec<Session>, ServiceError> {
+    let owner = request.user.unwrap_or(connection.user.as_ref().unwrap().id);
+    connection.require_self_or(&owner, Permission::ManageUsers)?;
+
+    connection.database.get()?.list_sessions(&owner)
+}
+
+pub fn revoke_session(connection: &mut UserConnection, request: UuidRequest) -> Result<(), ServiceError> {
+    let mut database = connection.database.get()?;
+    let session = database.query_session(&request.id)?;
+    connection.require_self_or(&session.owner, Permission::ManageUsers)?;
+
+    database.delete_session(&session.id)?;
+
+    if connection.session == Some(session.id) {
+        connection.session = None;
+        connection.user = None;
+    }
+
+    Ok(())
+}
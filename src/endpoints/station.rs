@@ -1,5 +1,6 @@
-use super::{UuidRequest, ServiceResponse, Station, UserConnection};
-use rand::{distributions::Alphanumeric, Rng};
+use super::{text_or_value, token, Change, Heartbeat, ListOptions, Page, Permission, Region, Review, ReviewState, ServiceError, Station, StationFilter, StationTokens, UserConnection, UuidRequest};
+
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -9,12 +10,23 @@ pub struct CreateStationRequest {
     pub lat: f64,
     pub lon: f64,
     pub region: u32,
+    /// administrators may place stations outside the bounds of the region
+    #[serde(default)]
+    pub override_bounds: bool,
+    /// store a station outside the bounds anyway and let an administrator review it
+    #[serde(default)]
+    pub request_review: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct ListStationsRequest {
-    pub desired_owner: Option<String>,
+    pub desired_owner: Option<Uuid>,
+    #[serde(default, deserialize_with = "text_or_value")]
     pub desired_region: Option<u32>,
+    #[serde(default, deserialize_with = "text_or_value")]
+    pub approved: Option<bool>,
+    #[serde(flatten)]
+    pub options: ListOptions,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -24,180 +36,399 @@ pub struct ModifyStation {
     pub lat: Option<f64>,
     pub lon: Option<f64>,
     pub region: Option<u32>,
+    #[serde(default)]
+    pub override_bounds: bool,
+    #[serde(default)]
+    pub request_review: bool,
+}
+
+/// the plaintext token is only ever handed out in this answer, the server keeps a hash
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StationTokenResponse {
+    pub id: Uuid,
+    pub token: String,
+    /// until then the token that was replaced is accepted as well
+    pub previous_expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GenerateTokenRequest {
+    pub id: Uuid,
+    /// hours the replaced token stays valid, defaults to `--token-grace-period`, 0 revokes it at
+    /// once, at most `MAX_GRACE_PERIOD`
+    pub grace_period: Option<u32>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-pub struct ApproveStation {
+pub struct TokenInfo {
+    pub created_at: Option<DateTime<Utc>>,
+    /// the current token never expires on its own, only when it is rotated
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StationTokensResponse {
+    pub id: Uuid,
+    pub current: TokenInfo,
+    pub previous: Option<TokenInfo>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct VerifyTokenRequest {
     pub id: Uuid,
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct VerifyTokenResponse {
+    pub valid: bool,
     pub approved: bool,
+    pub region: Option<u32>,
+    pub frequency: Option<u64>,
+    pub protocol: Option<String>,
+    /// seconds the caller may reuse this answer for the same station and token
+    pub cache_ttl: u32,
 }
 
+/// sent by the receivers themselves, authenticated with the station token instead of a login
 #[derive(Deserialize, Serialize, Debug)]
-pub struct UuidResponse {
+pub struct HeartbeatRequest {
     pub id: Uuid,
-    pub success: bool,
+    pub token: String,
+    pub uptime: u64,
+    pub version: String,
+    pub telegrams: u64,
+}
+
+/// which of the two tokens of a station was presented
+pub enum TokenMatch {
+    Current,
+    Previous(DateTime<Utc>),
 }
 
-fn write_result(response: bool, connection: &mut UserConnection) {
-    let serialized = serde_json::to_string(&ServiceResponse {
-        success: response,
-        message: None
-    }).unwrap();
+/// returns whether the location has to be reviewed, locations outside the region are only
+/// accepted if a reviewer overrides the bounds or the owner asks for a review
+fn check_location(
+    connection: &UserConnection,
+    region: &Region,
+    (lat, lon): (f64, f64),
+    override_bounds: bool,
+    request_review: bool,
+) -> Result<bool, ServiceError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(ServiceError::Validation(String::from("coordinates out of range")));
+    }
+
+    if region.contains(lat, lon) {
+        return Ok(false);
+    }
+
+    if override_bounds {
+        connection.require(Permission::ApproveStations)?;
+        return Ok(false);
+    }
+
+    if request_review {
+        return Ok(true);
+    }
 
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
+    Err(ServiceError::Validation(format!(
+        "station is outside of region {}, set request_review to let an administrator decide",
+        region.name
+    )))
 }
 
+pub fn create_station(connection: &mut UserConnection, request: CreateStationRequest) -> Result<StationTokenResponse, ServiceError> {
+    if !connection.user.as_ref().unwrap().email_verified {
+        return Err(ServiceError::EmailNotVerified);
+    }
+
+    let mut database = connection.database.get()?;
+
+    let region = database.query_region(&request.region)?;
+    let location_review = check_location(
+        connection,
+        &region,
+        (request.lat, request.lon),
+        request.override_bounds,
+        request.request_review,
+    )?;
+
+    let token = token::generate(32);
+    let station = Station {
+        token_hash: Some(token::hash(&token)),
+        id: Uuid::new_v4(),
+        name: request.name,
+        lat: request.lat,
+        lon: request.lon,
+        region: request.region,
+        owner: connection.user.as_ref().unwrap().id,
+        approved: false,
+        review_state: ReviewState::Pending,
+        last_seen: None,
+        online: false,
+        location_review,
+    };
+
+    database.create_station(&station)?;
+    connection.publish(Change::station(&station, "created"));
 
-fn owns_station(connection: &mut UserConnection, station_id: &Uuid) -> bool {
-    let result_station = connection
-        .database
-        .lock()
-        .unwrap()
-        .query_station(&station_id);
+    Ok(StationTokenResponse {
+        id: station.id,
+        token,
+        previous_expires_at: None,
+    })
+}
 
-    if result_station.is_none() {
-        return false;
+/// a station is online if its last heartbeat is younger than `--station-timeout`
+pub fn mark_online(connection: &UserConnection, stations: &mut [Station]) {
+    let cutoff = Utc::now() - Duration::seconds(connection.station_timeout as i64);
+    for station in stations.iter_mut() {
+        station.online = station.last_seen.is_some_and(|last_seen| last_seen > cutoff);
     }
+}
+
+pub fn list_stations(connection: &mut UserConnection, request: ListStationsRequest) -> Result<Page<Station>, ServiceError> {
+    let filter = StationFilter {
+        owner: request.desired_owner,
+        region: request.desired_region,
+        approved: request.approved,
+    };
 
-    let station = result_station.unwrap();
+    let mut page = connection.database.get()?.list_stations(&filter, &request.options)?;
 
-    station.owner == connection.user.as_ref().unwrap().id
+    mark_online(connection, &mut page.items);
+    Ok(page)
 }
 
-pub fn create_station(connection: &mut UserConnection, request: CreateStationRequest) {
-    if connection
-        .database
-        .lock()
-        .unwrap()
-        .check_region_exists(request.region)
-        && connection.user.is_some()
-    {
-        let random_token: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
-
-        let station = Station {
-            token: Some(random_token),
-            id: Uuid::new_v4(),
-            name: request.name,
-            lat: request.lat,
-            lon: request.lon,
-            region: request.region,
-            owner: connection.user.as_ref().unwrap().id,
-            approved: false,
-        };
-
-        let result = connection.database.lock().unwrap().create_station(&station);
-        let serialized = serde_json::to_string(&UuidResponse {
-            success: true,
-            id: station.id
-        }).unwrap();
-
-        connection
-            .socket
-            .write_message(tungstenite::Message::Text(serialized))
-            .unwrap();
+pub fn delete_station(connection: &mut UserConnection, request: UuidRequest) -> Result<(), ServiceError> {
+    let mut database = connection.database.get()?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
 
+    let station = database.query_station(&request.id)?;
+    database.delete_station(&request.id)?;
+    connection.publish(Change::station(&station, "deleted"));
+    Ok(())
+}
+
+pub fn modify_station(connection: &mut UserConnection, request: ModifyStation) -> Result<(), ServiceError> {
+    let mut database = connection.database.get()?;
+    let station = database.query_station(&request.id)?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
+
+    let (lat, lon) = (request.lat.unwrap_or(station.lat), request.lon.unwrap_or(station.lon));
+    let region = request.region.unwrap_or(station.region);
+
+    // the location is only checked again if it changed, or to settle a review with an override
+    let moved = request.lat.is_some() || request.lon.is_some() || request.region.is_some();
+    let location_review = if moved || request.override_bounds {
+        check_location(
+            connection,
+            &database.query_region(&region)?,
+            (lat, lon),
+            request.override_bounds,
+            request.request_review,
+        )?
     } else {
-        write_result(false, connection);
+        station.location_review
+    };
+
+    database.update_station(&Station {
+        id: request.id,
+        approved: station.approved,
+        review_state: station.review_state,
+        name: request.name.as_ref().unwrap_or(&station.name).to_string(),
+        lat,
+        lon,
+        region,
+        token_hash: None,
+        owner: station.owner,
+        last_seen: station.last_seen,
+        online: false,
+        location_review,
+    })?;
+
+    // owners answer a rejection or a change request by editing the station, an approved station
+    // that moved is approved again unless a reviewer moved it to a place that needs no review
+    let user_id = connection.user.as_ref().unwrap().id;
+    let relocated = (lat, lon, region) != (station.lat, station.lon, station.region);
+    let resubmitted = station.owner == user_id
+        && matches!(station.review_state, ReviewState::ChangesRequested | ReviewState::Rejected);
+    let requeued = station.review_state == ReviewState::Approved
+        && relocated
+        && (location_review || !connection.may(Permission::ApproveStations));
+    if resubmitted || requeued {
+        database.review_station(&Review {
+            station: station.id,
+            reviewer: Some(user_id),
+            from_state: station.review_state,
+            to_state: ReviewState::Pending,
+            comment: Some(String::from("the location changed")).filter(|_| requeued),
+            created_at: Utc::now(),
+        })?;
     }
+
+    connection.publish(Change::Station {
+        id: station.id,
+        owner: station.owner,
+        region,
+        previous_region: Some(station.region).filter(|previous| *previous != region),
+        review_state: if resubmitted || requeued { ReviewState::Pending } else { station.review_state },
+        action: "modified",
+    });
+
+    Ok(())
 }
 
-pub fn list_stations(connection: &mut UserConnection, request: ListStationsRequest) {
-    let data = connection
-        .database
-        .lock()
-        .unwrap()
-        .list_stations(request.desired_owner, request.desired_region);
-    
-    let serialized = serde_json::to_string(&data).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
-}
-
-pub fn delete_station(connection: &mut UserConnection, request: UuidRequest) {
-    let mut result_query = false;
-    if connection.user.as_ref().unwrap().is_admin() || owns_station(connection, &request.id) {
-        result_query = connection
-            .database
-            .lock()
-            .unwrap()
-            .delete_station(&request.id);
+/// longest grace period in hours a replaced station token can get
+const MAX_GRACE_PERIOD: u32 = 720;
+
+/// when a token replaced now stops working
+pub fn grace_period_end(grace_period: u32) -> Result<DateTime<Utc>, ServiceError> {
+    if grace_period > MAX_GRACE_PERIOD {
+        return Err(ServiceError::Validation(format!("grace_period is at most {} hours", MAX_GRACE_PERIOD)));
     }
 
-    write_result(result_query, connection);
+    Utc::now()
+        .checked_add_signed(Duration::hours(grace_period as i64))
+        .ok_or_else(|| ServiceError::Validation(String::from("grace_period is out of range")))
 }
 
-pub fn modify_station(connection: &mut UserConnection, request: ModifyStation) {
-    let result_station = connection
-        .database
-        .lock()
-        .unwrap()
-        .query_station(&request.id);
+/// rotates the token of a station, the old one keeps working for the grace period, refused
+/// while the token replaced before is still valid
+pub fn generate_token(connection: &mut UserConnection, request: GenerateTokenRequest) -> Result<StationTokenResponse, ServiceError> {
+    let mut database = connection.database.get()?;
 
-    if result_station.as_ref().is_none() {
-        write_result(false, connection);
-        return;
-    }
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
 
-    let station = result_station.unwrap();
-    if connection.user.as_ref().unwrap().is_admin() || owns_station(connection, &request.id) {
-        let response = connection
-            .database
-            .lock()
-            .unwrap()
-            .update_station(&Station {
-                id: request.id,
-                approved: connection.user.as_ref().unwrap().is_admin(),
-                name: request.name.as_ref().unwrap_or(&station.name).to_string(),
-                lat: request.lat.unwrap_or(station.lat),
-                lon: request.lon.unwrap_or(station.lon),
-                region: request.region.unwrap_or(station.region),
-                token: None,
-                owner: station.owner,
-            });
-        write_result(response, connection);
-    } else {
-        write_result(false, connection);
-    }
+    let grace_period = request.grace_period.unwrap_or(connection.token_grace_period);
+    let previous_expires_at = grace_period_end(grace_period)?;
+
+    let token = token::generate(32);
+    database.rotate_station_token(&request.id, &token::hash(&token), previous_expires_at)?;
+
+    Ok(StationTokenResponse {
+        id: request.id,
+        token,
+        previous_expires_at: (grace_period > 0).then_some(previous_expires_at),
+    })
 }
 
-pub fn approve_station(connection: &mut UserConnection, request: ApproveStation) {
-    if connection.user.as_ref().unwrap().is_admin() {
-        let response = connection
-            .database
-            .lock()
-            .unwrap()
-            .set_approved(&request.id, request.approved);
-        write_result(response, connection);
-    } else {
-        write_result(false, connection);
+pub fn station_tokens(connection: &mut UserConnection, request: UuidRequest) -> Result<StationTokensResponse, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
+
+    let tokens = database.query_station_tokens(&request.id)?;
+
+    Ok(StationTokensResponse {
+        id: request.id,
+        current: TokenInfo {
+            created_at: tokens.created_at,
+            expires_at: None,
+        },
+        previous: tokens.active_previous().map(|(_, expires_at)| TokenInfo {
+            created_at: tokens.previous_created_at,
+            expires_at: Some(expires_at),
+        }),
+    })
+}
+
+/// ends the grace period of the replaced token right away
+pub fn revoke_previous_token(connection: &mut UserConnection, request: UuidRequest) -> Result<(), ServiceError> {
+    let mut database = connection.database.get()?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
+
+    database.revoke_previous_station_token(&request.id)
+}
+
+pub fn match_token(tokens: &StationTokens, presented: &str) -> Option<TokenMatch> {
+    if tokens
+        .token_hash
+        .as_deref()
+        .is_some_and(|token_hash| token::verify(presented, token_hash))
+    {
+        return Some(TokenMatch::Current);
     }
+
+    let (previous_hash, expires_at) = tokens.active_previous()?;
+    token::verify(presented, previous_hash).then_some(TokenMatch::Previous(expires_at))
 }
 
-pub fn generate_token(connection: &mut UserConnection, request: UuidRequest) {
-    if connection.user.as_ref().unwrap().is_admin() || owns_station(connection, &request.id) {
-        let random_token: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
+/// lets the data sinks check the token a station sends with its telegrams, unknown stations and
+/// wrong tokens both just come back as invalid
+pub fn verify_token(connection: &mut UserConnection, request: VerifyTokenRequest) -> Result<VerifyTokenResponse, ServiceError> {
+    connection.require(Permission::VerifyTokens)?;
 
-        let response = connection
-            .database
-            .lock()
-            .unwrap()
-            .set_token(&request.id, &random_token);
+    let mut database = connection.database.get()?;
+    let invalid = VerifyTokenResponse {
+        valid: false,
+        approved: false,
+        region: None,
+        frequency: None,
+        protocol: None,
+        cache_ttl: connection.token_cache_ttl,
+    };
 
-        write_result(response, connection);
-    } else {
-        write_result(false, connection);
+    let tokens = match database.query_station_tokens(&request.id) {
+        Ok(tokens) => tokens,
+        Err(ServiceError::NotFound(_)) => return Ok(invalid),
+        Err(e) => return Err(e),
+    };
+
+    // an answer that relies on the previous token must not outlive its grace period
+    let cache_ttl = match match_token(&tokens, &request.token) {
+        Some(TokenMatch::Current) => connection.token_cache_ttl,
+        Some(TokenMatch::Previous(expires_at)) => {
+            let remaining = (expires_at - Utc::now()).num_seconds().max(0) as u32;
+            connection.token_cache_ttl.min(remaining)
+        }
+        None => return Ok(invalid),
+    };
+
+    let station = database.query_station(&request.id)?;
+    let region = database.query_region(&station.region)?;
+
+    Ok(VerifyTokenResponse {
+        valid: true,
+        approved: station.approved,
+        region: Some(region.id),
+        frequency: Some(region.frequency),
+        protocol: Some(region.protocol),
+        cache_ttl,
+    })
+}
+
+pub fn heartbeat(connection: &mut UserConnection, request: HeartbeatRequest) -> Result<(), ServiceError> {
+    let mut database = connection.database.get()?;
+
+    let tokens = match database.query_station_tokens(&request.id) {
+        Ok(tokens) => tokens,
+        Err(ServiceError::NotFound(_)) => return Err(ServiceError::Unauthenticated),
+        Err(e) => return Err(e),
+    };
+
+    if match_token(&tokens, &request.token).is_none() {
+        return Err(ServiceError::Unauthenticated);
     }
+
+    database.record_heartbeat(&Heartbeat {
+        station: request.id,
+        received_at: Utc::now(),
+        uptime: request.uptime,
+        version: request.version,
+        telegrams: request.telegrams,
+    })
+}
+
+/// the heartbeats of the last days, newest first and capped at 1000
+pub fn list_heartbeats(connection: &mut UserConnection, request: UuidRequest) -> Result<Vec<Heartbeat>, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    connection.require_station_owner_or(&mut database, &request.id, Permission::ManageStations)?;
+
+    database.list_heartbeats(&request.id)
 }
@@ -0,0 +1,37 @@
+use super::{ServiceError, Topic, UserConnection};
+
+/// topics the connection is subscribed to after the operation
+pub fn subscribe(connection: &mut UserConnection, topic: Topic) -> Result<Vec<Topic>, ServiceError> {
+    // http requests have nobody to push to
+    let socket = match &connection.socket {
+        Some(socket) => socket.clone(),
+        None => {
+            return Err(ServiceError::Validation(String::from(
+                "subscriptions are only available over the websocket",
+            )))
+        }
+    };
+
+    if let Some(permission) = topic.permission() {
+        connection.require(permission)?;
+    }
+
+    if let Topic::Region { region } = topic {
+        connection.database.get()?.query_region(&region)?;
+    }
+
+    let user = connection.user.as_ref().unwrap();
+    connection
+        .events
+        .subscribe(connection.id, socket, user.id, user.role.clone(), topic);
+    Ok(connection.events.topics(&connection.id))
+}
+
+pub fn unsubscribe(connection: &mut UserConnection, topic: Topic) -> Result<Vec<Topic>, ServiceError> {
+    connection.events.unsubscribe(&connection.id, &topic);
+    Ok(connection.events.topics(&connection.id))
+}
+
+pub fn list_subscriptions(connection: &mut UserConnection) -> Result<Vec<Topic>, ServiceError> {
+    Ok(connection.events.topics(&connection.id))
+}
@@ -0,0 +1,223 @@
+use super::totp;
+use super::user::{start_session, LoginResponse};
+use super::{token, Change, DataBaseConnection, Permission, Role, ServiceError, TokenPurpose, UserConnection};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// how many recovery codes are handed out when two-factor authentication is turned on
+const RECOVERY_CODES: usize = 10;
+
+/// operations left to accounts whose role requires two-factor authentication before it is set up
+const ENROLLMENT_OPERATIONS: &[&str] = &[
+    "user/session",
+    "user/logout",
+    "user/permissions",
+    "user/verify_email",
+    "user/resend_verification",
+    "user/totp/enroll",
+    "user/totp/confirm",
+];
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TotpEnrollment {
+    /// base32 secret for manual entry
+    pub secret: String,
+    /// `otpauth://` uri to show as QR code
+    pub uri: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+/// each code works once in place of an authenticator code, only their hashes are kept
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// users turn off their own second factor with a code, user managers can turn off the one of
+/// others without, e.g. after a lost phone
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DisableTotpRequest {
+    pub id: Option<Uuid>,
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TotpLoginRequest {
+    /// handed out by `user/login`
+    pub challenge: String,
+    /// code of the authenticator app or one of the recovery codes
+    pub code: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RolePolicyRequest {
+    pub role: Role,
+    pub require_totp: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RolePolicy {
+    pub role: Role,
+    pub permissions: &'static [Permission],
+    pub require_totp: bool,
+}
+
+/// accepts an authenticator code or a recovery code, either one is used up by this
+fn check_second_factor(database: &mut DataBaseConnection, user: &Uuid, code: &str) -> Result<bool, ServiceError> {
+    let state = database.query_totp(user)?;
+    let secret = match (&state.secret, state.enabled) {
+        (Some(secret), true) => secret,
+        _ => return Ok(false),
+    };
+
+    if let Some(step) = totp::verify(secret, code, state.last_step) {
+        return database.use_totp_step(user, step);
+    }
+
+    database.consume_recovery_code(user, &token::hash(code.trim()))
+}
+
+/// keeps accounts that have to use two-factor authentication to enrolling until they did
+pub fn check_totp_policy(connection: &UserConnection, operation: &str) -> Result<(), ServiceError> {
+    let user = match &connection.user {
+        Some(user) if !user.totp_enabled => user,
+        _ => return Ok(()),
+    };
+
+    if ENROLLMENT_OPERATIONS.contains(&operation) || !connection.database.get()?.role_requires_totp(&user.role)? {
+        return Ok(());
+    }
+
+    Err(ServiceError::TotpRequired)
+}
+
+/// second step of the login for accounts with two-factor authentication, failed codes count
+/// towards the lockout of the account like wrong passwords
+pub fn login_totp(connection: &mut UserConnection, request: TotpLoginRequest) -> Result<LoginResponse, ServiceError> {
+    let mut database = connection.database.get()?;
+    let address = connection.address.map(|address| address.ip());
+    let challenge = token::hash(&request.challenge);
+
+    let owner = database.peek_user_token(TokenPurpose::LoginChallenge, &challenge)?;
+    let user = database.query_user_by_id(&owner)?;
+
+    connection.throttle.check(&mut database, &user.name, address)?;
+
+    if !check_second_factor(&mut database, &owner, &request.code)? {
+        println!("failed second factor for {}", &user.name);
+        connection.throttle.failed(&mut database, &user.name, address)?;
+        return Err(ServiceError::Unauthenticated);
+    }
+
+    // fails if a parallel request with the same challenge was faster
+    database.consume_user_token(TokenPurpose::LoginChallenge, &challenge)?;
+    connection.throttle.succeeded(&mut database, &user.name, address)?;
+
+    start_session(connection, &mut database, user)
+}
+
+/// creates a new secret, it is only asked for at login after `user/totp/confirm`
+pub fn enroll_totp(connection: &mut UserConnection) -> Result<TotpEnrollment, ServiceError> {
+    let user = connection.user.clone().unwrap();
+    let secret = totp::generate_secret();
+
+    connection.database.get()?.set_pending_totp_secret(&user.id, &secret)?;
+
+    Ok(TotpEnrollment {
+        uri: totp::provisioning_uri(&connection.totp_issuer, &user.name, &secret),
+        secret,
+    })
+}
+
+/// proves the authenticator app got the secret and turns two-factor authentication on
+pub fn confirm_totp(connection: &mut UserConnection, request: TotpCodeRequest) -> Result<RecoveryCodesResponse, ServiceError> {
+    let user = connection.user.clone().unwrap();
+    let mut database = connection.database.get()?;
+
+    let state = database.query_totp(&user.id)?;
+    if state.enabled {
+        return Err(ServiceError::Conflict(String::from("two-factor authentication is already enabled")));
+    }
+    let secret = state
+        .secret
+        .ok_or_else(|| ServiceError::Validation(String::from("no enrollment started, call user/totp/enroll first")))?;
+
+    let accepted = match totp::verify(&secret, &request.code, state.last_step) {
+        Some(step) => database.use_totp_step(&user.id, step)?,
+        None => false,
+    };
+    if !accepted {
+        return Err(ServiceError::Validation(String::from("wrong code")));
+    }
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| token::generate(12)).collect();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| token::hash(code)).collect();
+    database.enable_totp(&user.id, &hashes)?;
+
+    connection.publish(Change::User {
+        id: user.id,
+        action: "totp_enabled",
+    });
+    Ok(RecoveryCodesResponse { recovery_codes })
+}
+
+pub fn disable_totp(connection: &mut UserConnection, request: DisableTotpRequest) -> Result<(), ServiceError> {
+    let current = connection.user.clone().unwrap();
+    let id = request.id.unwrap_or(current.id);
+    let mut database = connection.database.get()?;
+
+    if id == current.id && current.totp_enabled {
+        let address = connection.address.map(|address| address.ip());
+        connection.throttle.check(&mut database, &current.name, address)?;
+
+        let code = request
+            .code
+            .ok_or_else(|| ServiceError::Validation(String::from("code is required")))?;
+        if !check_second_factor(&mut database, &id, &code)? {
+            connection.throttle.failed(&mut database, &current.name, address)?;
+            return Err(ServiceError::Unauthenticated);
+        }
+    } else if id != current.id {
+        connection.require(Permission::ManageUsers)?;
+        database.query_user_by_id(&id)?;
+    }
+
+    database.disable_totp(&id)?;
+
+    connection.publish(Change::User {
+        id,
+        action: "totp_disabled",
+    });
+    Ok(())
+}
+
+pub fn list_role_policies(connection: &mut UserConnection) -> Result<Vec<RolePolicy>, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    [Role::Administrator, Role::Moderator, Role::Service, Role::User]
+        .into_iter()
+        .map(|role| {
+            Ok(RolePolicy {
+                permissions: role.permissions(),
+                require_totp: database.role_requires_totp(&role)?,
+                role,
+            })
+        })
+        .collect()
+}
+
+/// makes two-factor authentication mandatory for a role, members without it are limited to
+/// setting it up on their next request
+pub fn set_role_policy(connection: &mut UserConnection, request: RolePolicyRequest) -> Result<(), ServiceError> {
+    connection.require(Permission::ManageUsers)?;
+
+    connection
+        .database
+        .get()?
+        .set_role_requires_totp(&request.role, request.require_totp)
+}
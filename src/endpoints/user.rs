@@ -1,19 +1,21 @@
-use super::{Role, ServiceResponse, User, UserConnection};
-
-use pbkdf2::{
-    password_hash::{Encoding, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Pbkdf2,
-};
+use super::throttle::{account_key, address_key};
+use super::verification::send_verification;
+use super::{token, Change, DataBaseConnection, RegistrationMode, ListOptions, Page, Permission, Role, ServiceError, TokenPurpose, UuidResponse, User, UserConnection, Verification};
 
+use chrono::{DateTime, Duration, Utc};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use std::net::IpAddr;
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct RegisterUserRequest {
     pub name: String,
     pub email: String,
     pub password: String,
+    /// required with `--registration invite-only`
+    pub invite: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -22,6 +24,33 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LoginResponse {
+    pub id: Uuid,
+    /// opaque session token, used for `user/resume` and as bearer token for the http api
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// the password was right but the account wants a code as well, the challenge goes to
+/// `user/login_totp` together with it
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TotpChallengeResponse {
+    pub totp_required: bool,
+    pub challenge: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum LoginResult {
+    Session(LoginResponse),
+    TotpChallenge(TotpChallengeResponse),
+}
+
+/// minutes between password and code
+const TOTP_CHALLENGE_LIFETIME: i64 = 5;
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ModifyUserRequest {
     pub id: Uuid,
@@ -29,227 +58,240 @@ pub struct ModifyUserRequest {
     pub email: Option<String>,
     pub password: Option<String>,
     pub role: Option<Role>,
+    /// lets user managers confirm an address by hand
+    pub email_verified: Option<bool>,
 }
 
+/// either the account, the client address or both
 #[derive(Deserialize, Serialize, Debug)]
-pub struct UuidRequest {
-    pub id: Uuid,
+pub struct UnlockLoginRequest {
+    pub id: Option<Uuid>,
+    pub address: Option<IpAddr>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-pub struct UuidResponse {
+pub struct UuidRequest {
     pub id: Uuid,
-    pub success: bool
 }
 
+pub fn validate_email(email: &str) -> Result<(), ServiceError> {
+    let email_regex = Regex::new(
+        r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
+    )
+    .unwrap();
 
-fn hash_password(password: &String) -> String {
-    let default_salt_path = String::from("/run/secrets/clicky_bunty_salt");
-    let salt_path = std::env::var("SALT_PATH").unwrap_or(default_salt_path);
-    let salt = SaltString::b64_encode(std::fs::read(salt_path).unwrap().as_slice()).unwrap();
+    if !email_regex.is_match(email) {
+        return Err(ServiceError::Validation("invalid email address".to_string()));
+    }
 
-    let password_hash = Pbkdf2
-        .hash_password(password.as_bytes(), &salt)
-        .unwrap()
-        .to_string();
-    PasswordHash::new(&password_hash).unwrap().to_string()
+    Ok(())
 }
 
-pub fn create_user(connection: &mut UserConnection, request: RegisterUserRequest) {
-    if connection
-        .database
-        .lock()
-        .unwrap()
-        .check_user_exists(&request.name)
-    {
-        let serialized = serde_json::to_string(&ServiceResponse { success: false, message: Some("name already taken".to_string()) }).unwrap();
-        connection
-            .socket
-            .write_message(tungstenite::Message::Text(serialized))
-            .unwrap();
-
-        return;
+/// new accounts always get the `User` role, administrators are created with `create-admin`
+pub fn create_user(connection: &mut UserConnection, request: RegisterUserRequest) -> Result<UuidResponse, ServiceError> {
+    if connection.registration == RegistrationMode::Closed {
+        return Err(ServiceError::PermissionDenied);
     }
 
-    let email_regex = Regex::new(
-        r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
-    )
-    .unwrap();
+    let mut database = connection.database.get()?;
 
-    if !email_regex.is_match(&request.email) {
-        return;
+    if database.check_user_exists(&request.name)? {
+        return Err(ServiceError::Conflict("name already taken".to_string()));
     }
 
-    let password_hash = hash_password(&request.password);
+    validate_email(&request.email)?;
 
-    let role = if connection.database.lock().unwrap().first_user() {
-        Role::Administrator
-    } else {
-        Role::User
-    };
+    let password_hash = connection.passwords.hash(&request.password)?;
 
-    let user = User {
+    let mut user = User {
         id: Uuid::new_v4(),
         name: request.name,
         email: request.email,
         password: password_hash,
-        role: role,
+        role: Role::User,
+        email_verified: false,
+        totp_enabled: false,
     };
 
-    let result = connection.database.lock().unwrap().create_user(&user);
-
-    let serialized = serde_json::to_string(&UuidResponse { id: user.id, success: result }).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
-}
-
-pub fn login(connection: &mut UserConnection, request: LoginRequest) {
-    match connection
-        .database
-        .lock()
-        .unwrap()
-        .query_user(&request.name)
-    {
-        Some(user) => {
-            println!("Found unter with this name !");
-            let password_hash = PasswordHash::parse(&user.password, Encoding::B64).unwrap();
-            match Pbkdf2.verify_password(request.password.as_bytes(), &password_hash) {
-                Ok(_) => {
-                    connection.user = Some(user.clone());
-                    let serialized =
-                        serde_json::to_string(&UuidResponse { id: user.id, success: true }).unwrap();
-                    connection
-                        .socket
-                        .write_message(tungstenite::Message::Text(serialized))
-                        .unwrap();
-                    return;
-                }
-                _ => {
-                    println!("Password does not match");
-                }
-            }
+    match (connection.registration, &request.invite) {
+        (RegistrationMode::InviteOnly, None) => {
+            return Err(ServiceError::Validation(String::from("registration needs an invite")));
+        }
+        // the invite is only used up together with the insert, a failed one leaves it valid
+        (RegistrationMode::InviteOnly, Some(invite)) => database.create_invited_user(&mut user, &token::hash(invite))?,
+        _ => database.create_user(&user)?,
+    }
+
+    // the account exists either way, a lost mail can be sent again with `user/resend_verification`
+    if !user.email_verified {
+        if let Err(e) = send_verification(connection, &mut database, &user) {
+            println!("could not send verification mail to {} {:?}", user.name, e);
         }
+    }
+
+    Ok(UuidResponse { id: user.id })
+}
+
+/// wrong names and wrong passwords are answered the same way, both count towards the lockout
+/// of the name and the address of the client
+pub fn login(connection: &mut UserConnection, request: LoginRequest) -> Result<LoginResult, ServiceError> {
+    let mut database = connection.database.get()?;
+    let address = connection.address.map(|address| address.ip());
+
+    connection.throttle.check(&mut database, &request.name, address)?;
+
+    let user = match database.query_user(&request.name) {
+        Ok(user) => Some(user),
+        Err(ServiceError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let verification = match &user {
+        Some(user) => connection.passwords.verify(&request.password, &user.password),
+        None => connection.passwords.verify_dummy(&request.password),
+    };
+
+    let user = match (user, &verification) {
+        (Some(user), Verification::Valid | Verification::Outdated) => user,
         _ => {
-            println!("No user found ! {}", &request.name);
+            println!("failed login for {}", &request.name);
+            connection.throttle.failed(&mut database, &request.name, address)?;
+            return Err(ServiceError::Unauthenticated);
         }
+    };
+
+    if verification == Verification::Outdated {
+        println!("Upgrading password hash of {}", &user.name);
+        let password_hash = connection.passwords.hash(&request.password)?;
+        database.set_password(&user.id, &password_hash)?;
     }
-    let serialized = serde_json::to_string(&ServiceResponse { success: false, message: Some("could not login user name or password wrong".to_string()) }).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
+
+    // the failures are only forgotten after the code, otherwise the password alone would reset
+    // the lockout for guessing codes
+    if user.totp_enabled {
+        let challenge = token::generate(32);
+        let expires_at = Utc::now() + Duration::minutes(TOTP_CHALLENGE_LIFETIME);
+        database.create_user_token(&user.id, TokenPurpose::LoginChallenge, &token::hash(&challenge), &expires_at)?;
+
+        return Ok(LoginResult::TotpChallenge(TotpChallengeResponse {
+            totp_required: true,
+            challenge,
+            expires_at,
+        }));
+    }
+
+    connection.throttle.succeeded(&mut database, &request.name, address)?;
+    start_session(connection, &mut database, user).map(LoginResult::Session)
+}
+
+/// logs the connection in after all factors were checked
+pub fn start_session(connection: &mut UserConnection, database: &mut DataBaseConnection, user: User) -> Result<LoginResponse, ServiceError> {
+    let (session, token) = connection.sessions.create(database, user.id)?;
+    connection.session = Some(session.id);
+    connection.user = Some(user);
+
+    Ok(LoginResponse {
+        id: session.owner,
+        token,
+        expires_at: session.expires_at,
+    })
 }
 
-pub fn get_session(connection: &mut UserConnection) {
-    let serialized = serde_json::to_string(&UuidRequest {
+pub fn get_session(connection: &mut UserConnection) -> Result<UuidResponse, ServiceError> {
+    Ok(UuidResponse {
         id: connection.user.as_ref().unwrap().id,
-    }).unwrap();
-    connection
-        .socket
-        .write_message(tungstenite::Message::Text(serialized))
-        .unwrap();
-}
-
-pub fn delete_user(connection: &mut UserConnection, delete_request: UuidRequest) {
-    let user_id = connection.user.as_ref().unwrap().id;
-
-    if connection
-        .database
-        .lock()
-        .unwrap()
-        .is_administrator(&user_id)
-        || user_id == delete_request.id
-    {
-        connection
-            .database
-            .lock()
-            .unwrap()
-            .delete_user(&delete_request.id);
-    } else {
-        let serialized = serde_json::to_string(&ServiceResponse { success: false, message: Some("you are not administrator or this user".to_string()) }).unwrap();
-        connection
-            .socket
-            .write_message(tungstenite::Message::Text(serialized))
-            .unwrap();
-    }
+    })
 }
 
-pub fn modify_user(connection: &mut UserConnection, modify_request: ModifyUserRequest) {
-    let user_struct_result = connection
-        .database
-        .lock()
-        .unwrap()
-        .query_user_by_id(&modify_request.id);
+pub fn delete_user(connection: &mut UserConnection, delete_request: UuidRequest) -> Result<(), ServiceError> {
+    connection.require_self_or(&delete_request.id, Permission::ManageUsers)?;
+    let mut database = connection.database.get()?;
+
+    database.delete_user(&delete_request.id)?;
+    connection.publish(Change::User {
+        id: delete_request.id,
+        action: "deleted",
+    });
+    Ok(())
+}
 
-    if user_struct_result.is_none() {
-        return;
+pub fn modify_user(connection: &mut UserConnection, modify_request: ModifyUserRequest) -> Result<(), ServiceError> {
+    connection.require_self_or(&modify_request.id, Permission::ManageUsers)?;
+    if modify_request.role.is_some() || modify_request.email_verified.is_some() {
+        connection.require(Permission::ManageUsers)?;
+    }
+    if let Some(email) = &modify_request.email {
+        validate_email(email)?;
     }
 
-    let user_struct = user_struct_result.unwrap();
-    let user_id = connection.user.as_ref().unwrap().id;
-    let admin = connection
-        .database
-        .lock()
-        .unwrap()
-        .is_administrator(&user_id);
-
-    if admin || user_id == modify_request.id {
-        if !admin && modify_request.role.is_some() {
-            // only admins can change the role of a suer
-            let serialized = serde_json::to_string(&ServiceResponse { success: false, message: Some("you are not administrator or this user".to_string()) }).unwrap();
-            connection
-                .socket
-                .write_message(tungstenite::Message::Text(serialized))
-                .unwrap();
-
-            return;
-        }
+    let mut database = connection.database.get()?;
 
-        let hashed_password: String;
-        match &modify_request.password {
-            Some(password) => {
-                hashed_password = hash_password(&password);
-            }
-            _ => {
-                hashed_password = user_struct.password;
-            }
-        }
+    let user_struct = database.query_user_by_id(&modify_request.id)?;
+
+    let hashed_password = match &modify_request.password {
+        Some(password) => connection.passwords.hash(password)?,
+        _ => user_struct.password,
+    };
+
+    // a new address has to be verified again
+    let email_changed = modify_request
+        .email
+        .as_ref()
+        .is_some_and(|email| *email != user_struct.email);
+    let email_verified = modify_request
+        .email_verified
+        .unwrap_or(user_struct.email_verified && !email_changed);
 
-        connection.database.lock().unwrap().update_user(&User {
-            id: modify_request.id,
-            name: modify_request.name.clone().unwrap_or(user_struct.name),
-            email: modify_request.email.clone().unwrap_or(user_struct.email),
-            password: hashed_password,
-            role: modify_request.role.clone().unwrap_or(user_struct.role),
-        });
-    } else {
-        let serialized = serde_json::to_string(&ServiceResponse { success: false, message: Some("you are not administrator or this user".to_string()) }).unwrap();
-        connection
-            .socket
-            .write_message(tungstenite::Message::Text(serialized))
-            .unwrap();
+    let user = User {
+        id: modify_request.id,
+        name: modify_request.name.clone().unwrap_or(user_struct.name),
+        email: modify_request.email.clone().unwrap_or(user_struct.email),
+        password: hashed_password,
+        role: modify_request.role.clone().unwrap_or(user_struct.role),
+        email_verified,
+        totp_enabled: user_struct.totp_enabled,
+    };
+    database.update_user(&user)?;
+    // open sockets of the user may hold topics the new role does not allow
+    connection.events.set_role(&user.id, &user.role);
+
+    // the change is stored already, a lost mail can be sent again with `user/resend_verification`
+    if email_changed && !email_verified {
+        if let Err(e) = send_verification(connection, &mut database, &user) {
+            println!("could not send verification mail to {} {:?}", user.name, e);
+        }
     }
+
+    connection.publish(Change::User {
+        id: modify_request.id,
+        action: "modified",
+    });
+    Ok(())
 }
 
-pub fn list_users(connection: &mut UserConnection) {
-    println!("current user: {:?}", &connection.user);
-    if connection.user.as_ref().unwrap().role == Role::Administrator {
-            let users = connection.database.lock().unwrap().list_users();
+pub fn list_users(connection: &mut UserConnection, options: ListOptions) -> Result<Page<User>, ServiceError> {
+    connection.require(Permission::ManageUsers)?;
 
-            let serialized = serde_json::to_string(&users).unwrap();
-            connection
-                .socket
-                .write_message(tungstenite::Message::Text(serialized))
-                .unwrap();
+    connection.database.get()?.list_users(&options)
+}
+
+/// lifts the lockout after failed logins
+pub fn unlock_login(connection: &mut UserConnection, request: UnlockLoginRequest) -> Result<(), ServiceError> {
+    connection.require(Permission::ManageUsers)?;
 
-    } else {
-        let serialized = serde_json::to_string(&ServiceResponse { success: false, message: Some("you are not administrator".to_string()) }).unwrap();
-        connection
-            .socket
-            .write_message(tungstenite::Message::Text(serialized))
-            .unwrap();
+    if request.id.is_none() && request.address.is_none() {
+        return Err(ServiceError::Validation(String::from("id or address is required")));
     }
-}
 
+    let mut database = connection.database.get()?;
+
+    if let Some(id) = request.id {
+        let user = database.query_user_by_id(&id)?;
+        database.clear_login_failures(&account_key(&user.name))?;
+    }
+    if let Some(address) = request.address {
+        database.clear_login_failures(&address_key(&address))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use super::{token, DataBaseConnection, Mail, ServiceError, TokenPurpose, User, UserConnection, UuidResponse};
+
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TokenRequest {
+    pub token: String,
+}
+
+/// mails a fresh verification code to the address of the user, a code sent before stops working
+pub fn send_verification(connection: &UserConnection, database: &mut DataBaseConnection, user: &User) -> Result<(), ServiceError> {
+    let token = token::generate(32);
+    let expires_at = Utc::now() + Duration::hours(connection.verification_lifetime);
+    database.create_user_token(&user.id, TokenPurpose::VerifyEmail, &token::hash(&token), &expires_at)?;
+
+    let link = match &connection.public_url {
+        Some(url) => format!(
+            "Open this link to confirm it:\n\n{}/users/verify?token={}\n\nor enter this code: ",
+            url.trim_end_matches('/'),
+            token
+        ),
+        None => String::from("Enter this code to confirm it: "),
+    };
+
+    connection.mailer.send(&Mail {
+        to: user.email.clone(),
+        subject: String::from("Confirm your email address"),
+        body: format!(
+            "Hello {},\n\nthis address was given for your account. {}{}\n\nThe code is valid until {}.\n",
+            user.name,
+            link,
+            token,
+            expires_at.to_rfc3339()
+        ),
+    })
+}
+
+/// marks the address of the owner of the code as verified, works without login so the link can
+/// be opened anywhere
+pub fn verify_email(connection: &mut UserConnection, request: TokenRequest) -> Result<UuidResponse, ServiceError> {
+    let mut database = connection.database.get()?;
+
+    let owner = database.consume_user_token(TokenPurpose::VerifyEmail, &token::hash(&request.token))?;
+    database.set_email_verified(&owner, true)?;
+
+    if let Some(user) = connection.user.as_mut().filter(|user| user.id == owner) {
+        user.email_verified = true;
+    }
+
+    Ok(UuidResponse { id: owner })
+}
+
+pub fn resend_verification(connection: &mut UserConnection) -> Result<(), ServiceError> {
+    let user = connection.user.clone().unwrap();
+    if user.email_verified {
+        return Err(ServiceError::Validation(String::from("email address is already verified")));
+    }
+
+    let mut database = connection.database.get()?;
+    send_verification(connection, &mut database, &user)
+}
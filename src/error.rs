@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use postgres::error::SqlState;
+use serde::Serialize;
+
+use std::fmt;
+
+/// everything that can go wrong while handling a request, it is passed from the database layer
+/// through the endpoints and ends up as the error code in the response
+#[derive(Debug)]
+pub enum ServiceError {
+    /// the referenced entity (station, region, user, ...) does not exist
+    NotFound(&'static str),
+    /// a unique constraint was violated, e.g. the user name is already taken
+    Conflict(String),
+    /// the entity references something that does not exist or is still referenced
+    ForeignKeyViolation(String),
+    /// the authenticated user is not allowed to do this
+    PermissionDenied,
+    /// the presented credentials or token are not valid
+    Unauthenticated,
+    /// the account has to confirm its email address first
+    EmailNotVerified,
+    /// the role of the account requires two-factor authentication, it has to be set up first
+    TotpRequired,
+    /// too many failed logins for the account or from the address, locked until the given time
+    TooManyAttempts(DateTime<Utc>),
+    /// the request itself is malformed or contains invalid values
+    Validation(String),
+    /// database or server failure, details are only logged and never sent to the client
+    Internal,
+}
+
+/// stable machine readable error codes, the frontend switches on these
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotFound,
+    Conflict,
+    ForeignKeyViolation,
+    PermissionDenied,
+    Unauthenticated,
+    EmailNotVerified,
+    TotpRequired,
+    TooManyAttempts,
+    Validation,
+    Internal,
+}
+
+impl ServiceError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServiceError::NotFound(_) => ErrorCode::NotFound,
+            ServiceError::Conflict(_) => ErrorCode::Conflict,
+            ServiceError::ForeignKeyViolation(_) => ErrorCode::ForeignKeyViolation,
+            ServiceError::PermissionDenied => ErrorCode::PermissionDenied,
+            ServiceError::Unauthenticated => ErrorCode::Unauthenticated,
+            ServiceError::EmailNotVerified => ErrorCode::EmailNotVerified,
+            ServiceError::TotpRequired => ErrorCode::TotpRequired,
+            ServiceError::TooManyAttempts(_) => ErrorCode::TooManyAttempts,
+            ServiceError::Validation(_) => ErrorCode::Validation,
+            ServiceError::Internal => ErrorCode::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::NotFound(entity) => write!(f, "{} does not exist", entity),
+            ServiceError::Conflict(message) => write!(f, "conflict: {}", message),
+            ServiceError::ForeignKeyViolation(message) => write!(f, "invalid reference: {}", message),
+            ServiceError::PermissionDenied => write!(f, "permission denied"),
+            ServiceError::Unauthenticated => write!(f, "not authenticated"),
+            ServiceError::EmailNotVerified => write!(f, "email address is not verified yet"),
+            ServiceError::TotpRequired => write!(f, "two-factor authentication has to be enabled first"),
+            ServiceError::TooManyAttempts(until) => {
+                write!(f, "too many failed attempts, try again after {}", until.to_rfc3339())
+            }
+            ServiceError::Validation(message) => write!(f, "invalid request: {}", message),
+            ServiceError::Internal => write!(f, "internal server error"),
+        }
+    }
+}
+
+impl From<postgres::Error> for ServiceError {
+    fn from(e: postgres::Error) -> Self {
+        let detail = e
+            .as_db_error()
+            .map(|db_error| db_error.detail().unwrap_or(db_error.message()).to_string())
+            .unwrap_or_default();
+
+        match e.code() {
+            Some(&SqlState::UNIQUE_VIOLATION) => ServiceError::Conflict(detail),
+            Some(&SqlState::FOREIGN_KEY_VIOLATION) => ServiceError::ForeignKeyViolation(detail),
+            _ => {
+                println!("Error from database: {:?}", e);
+                ServiceError::Internal
+            }
+        }
+    }
+}
+
+impl From<r2d2::Error> for ServiceError {
+    fn from(e: r2d2::Error) -> Self {
+        println!("could not check out database connection {:?}", e);
+        ServiceError::Internal
+    }
+}
@@ -0,0 +1,323 @@
+use super::{Permission, ReviewState, Role, ServiceResponse, Station};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc::UnboundedSender;
+use tungstenite::Message;
+use uuid::Uuid;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// what a websocket client can subscribe to
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(tag = "topic", rename_all = "snake_case")]
+pub enum Topic {
+    /// changes to stations owned by the subscribed user
+    MyStations,
+    /// changes to the region itself and to every station in it
+    Region { region: u32 },
+    /// stations waiting for a review and every review decision, needs `approve_stations`
+    ApprovalQueue,
+    /// changes to the account of the subscribed user
+    MyAccount,
+}
+
+impl Topic {
+    /// what the subscriber has to be allowed to receive the topic
+    pub fn permission(&self) -> Option<Permission> {
+        match self {
+            Topic::ApprovalQueue => Some(Permission::ApproveStations),
+            _ => None,
+        }
+    }
+
+    fn allowed(&self, role: &Role) -> bool {
+        self.permission()
+            .map_or(true, |permission| role.permissions().contains(&permission))
+    }
+}
+
+/// a mutation done by one of the endpoints, routed to the matching topics
+#[derive(Debug, Clone)]
+pub enum Change {
+    Station {
+        id: Uuid,
+        owner: Uuid,
+        region: u32,
+        /// set when the station moved so the old region hears about it as well
+        previous_region: Option<u32>,
+        review_state: ReviewState,
+        action: &'static str,
+    },
+    Region {
+        id: u32,
+        action: &'static str,
+    },
+    User {
+        id: Uuid,
+        action: &'static str,
+    },
+}
+
+impl Change {
+    pub fn station(station: &Station, action: &'static str) -> Change {
+        Change::Station {
+            id: station.id,
+            owner: station.owner,
+            region: station.region,
+            previous_region: None,
+            review_state: station.review_state,
+            action,
+        }
+    }
+
+    fn matches(&self, topic: &Topic, user: &Uuid) -> bool {
+        match (self, topic) {
+            (Change::Station { owner, .. }, Topic::MyStations) => owner == user,
+            (
+                Change::Station {
+                    region, previous_region, ..
+                },
+                Topic::Region { region: subscribed },
+            ) => region == subscribed || previous_region.as_ref() == Some(subscribed),
+            (Change::Station { review_state, action, .. }, Topic::ApprovalQueue) => {
+                *review_state == ReviewState::Pending || *action == "reviewed"
+            }
+            (Change::Region { id, .. }, Topic::Region { region }) => id == region,
+            (Change::User { id, .. }, Topic::MyAccount) => id == user,
+            _ => false,
+        }
+    }
+
+    fn event(&self, topic: &Topic) -> (String, serde_json::Value) {
+        match self {
+            Change::Station {
+                id,
+                region,
+                review_state,
+                action,
+                ..
+            } => (
+                format!("event/station/{}", action),
+                json!({ "subscription": topic, "id": id, "region": region, "review_state": review_state }),
+            ),
+            Change::Region { id, action } => (
+                format!("event/region/{}", action),
+                json!({ "subscription": topic, "id": id }),
+            ),
+            Change::User { id, action } => (
+                format!("event/user/{}", action),
+                json!({ "subscription": topic, "id": id }),
+            ),
+        }
+    }
+}
+
+struct Subscriber {
+    socket: UnboundedSender<Message>,
+    user: Uuid,
+    /// kept up to date with `set_role` so topics are never delivered past a demotion
+    role: Role,
+    topics: HashSet<Topic>,
+}
+
+/// keeps track of which websocket connection listens to which topics and pushes the
+/// changes made by the endpoints to them
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<HashMap<Uuid, Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self, connection: Uuid, socket: UnboundedSender<Message>, user: Uuid, role: Role, topic: Topic) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let subscriber = subscribers.entry(connection).or_insert_with(|| Subscriber {
+            socket,
+            user,
+            role: role.clone(),
+            topics: HashSet::new(),
+        });
+
+        // a different login on the same socket starts over
+        if subscriber.user != user {
+            subscriber.user = user;
+            subscriber.topics.clear();
+        }
+        subscriber.role = role;
+
+        subscriber.topics.insert(topic);
+    }
+
+    /// applies a role change to every connection of the user, topics the new role does not
+    /// allow are dropped
+    pub fn set_role(&self, user: &Uuid, role: &Role) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        for subscriber in subscribers.values_mut().filter(|subscriber| subscriber.user == *user) {
+            if subscriber.role != *role {
+                subscriber.role = role.clone();
+                subscriber.topics.retain(|topic| topic.allowed(role));
+            }
+        }
+    }
+
+    pub fn unsubscribe(&self, connection: &Uuid, topic: &Topic) {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(connection) {
+            subscriber.topics.remove(topic);
+        }
+    }
+
+    pub fn topics(&self, connection: &Uuid) -> Vec<Topic> {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .get(connection)
+            .map(|subscriber| subscriber.topics.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// forgets every subscription of a connection, on logout and when the socket closes
+    pub fn disconnect(&self, connection: &Uuid) {
+        self.subscribers.lock().unwrap().remove(connection);
+    }
+
+    pub fn publish(&self, change: Change) {
+        let subscribers = self.subscribers.lock().unwrap();
+
+        for subscriber in subscribers.values() {
+            // one event per connection even if several of its topics match
+            let topic = match subscriber
+                .topics
+                .iter()
+                .find(|topic| topic.allowed(&subscriber.role) && change.matches(topic, &subscriber.user))
+            {
+                Some(topic) => topic,
+                None => continue,
+            };
+
+            let (operation, data) = change.event(topic);
+            let response = ServiceResponse::new(operation, None, Ok(data));
+            if subscriber
+                .socket
+                .send(Message::Text(serde_json::to_string(&response).unwrap()))
+                .is_err()
+            {
+                println!("subscriber already disconnected dropping event");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn station_change(owner: Uuid, region: u32, previous_region: Option<u32>, review_state: ReviewState) -> Change {
+        Change::Station {
+            id: Uuid::new_v4(),
+            owner,
+            region,
+            previous_region,
+            review_state,
+            action: "modified",
+        }
+    }
+
+    fn events(receiver: &mut UnboundedReceiver<Message>) -> Vec<serde_json::Value> {
+        let mut events = Vec::new();
+        while let Ok(Message::Text(text)) = receiver.try_recv() {
+            events.push(serde_json::from_str(&text).unwrap());
+        }
+        events
+    }
+
+    #[test]
+    fn approval_queue_needs_approve_stations() {
+        assert!(Topic::ApprovalQueue.allowed(&Role::Administrator));
+        assert!(Topic::ApprovalQueue.allowed(&Role::Moderator));
+        assert!(!Topic::ApprovalQueue.allowed(&Role::Service));
+        assert!(!Topic::ApprovalQueue.allowed(&Role::User));
+
+        for topic in [Topic::MyStations, Topic::Region { region: 1 }, Topic::MyAccount] {
+            assert!(topic.allowed(&Role::User), "{:?}", topic);
+        }
+    }
+
+    #[test]
+    fn station_moves_reach_the_old_and_the_new_region() {
+        let user = Uuid::new_v4();
+        let change = station_change(Uuid::new_v4(), 2, Some(1), ReviewState::Approved);
+
+        assert!(change.matches(&Topic::Region { region: 1 }, &user));
+        assert!(change.matches(&Topic::Region { region: 2 }, &user));
+        assert!(!change.matches(&Topic::Region { region: 3 }, &user));
+        assert!(!station_change(user, 2, None, ReviewState::Approved).matches(&Topic::Region { region: 1 }, &user));
+    }
+
+    #[test]
+    fn approval_queue_sees_pending_stations_and_reviews() {
+        let user = Uuid::new_v4();
+        assert!(station_change(user, 1, None, ReviewState::Pending).matches(&Topic::ApprovalQueue, &user));
+        assert!(!station_change(user, 1, None, ReviewState::Approved).matches(&Topic::ApprovalQueue, &user));
+
+        let reviewed = Change::Station {
+            id: Uuid::new_v4(),
+            owner: user,
+            region: 1,
+            previous_region: None,
+            review_state: ReviewState::Rejected,
+            action: "reviewed",
+        };
+        assert!(reviewed.matches(&Topic::ApprovalQueue, &user));
+    }
+
+    #[test]
+    fn publish_notifies_the_previous_region() {
+        let bus = EventBus::default();
+        let (socket, mut receiver) = unbounded_channel();
+        bus.subscribe(Uuid::new_v4(), socket, Uuid::new_v4(), Role::User, Topic::Region { region: 1 });
+
+        bus.publish(station_change(Uuid::new_v4(), 2, Some(1), ReviewState::Approved));
+        bus.publish(station_change(Uuid::new_v4(), 3, None, ReviewState::Approved));
+
+        let events = events(&mut receiver);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["operation"], "event/station/modified");
+        assert_eq!(events[0]["data"]["region"], 2);
+    }
+
+    #[test]
+    fn demotion_drops_the_approval_queue() {
+        let bus = EventBus::default();
+        let (connection, user) = (Uuid::new_v4(), Uuid::new_v4());
+        let (socket, mut receiver) = unbounded_channel();
+        bus.subscribe(connection, socket.clone(), user, Role::Moderator, Topic::ApprovalQueue);
+        bus.subscribe(connection, socket, user, Role::Moderator, Topic::MyStations);
+
+        bus.publish(station_change(Uuid::new_v4(), 1, None, ReviewState::Pending));
+        assert_eq!(events(&mut receiver).len(), 1);
+
+        bus.set_role(&user, &Role::User);
+        assert_eq!(bus.topics(&connection), vec![Topic::MyStations]);
+
+        bus.publish(station_change(Uuid::new_v4(), 1, None, ReviewState::Pending));
+        assert!(events(&mut receiver).is_empty());
+
+        bus.publish(station_change(user, 1, None, ReviewState::Pending));
+        assert_eq!(events(&mut receiver).len(), 1);
+    }
+
+    #[test]
+    fn set_role_leaves_other_users_alone() {
+        let bus = EventBus::default();
+        let (connection, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (socket, _receiver) = unbounded_channel();
+        bus.subscribe(connection, socket, Uuid::new_v4(), Role::Moderator, Topic::ApprovalQueue);
+
+        bus.set_role(&other, &Role::User);
+        assert_eq!(bus.topics(&connection), vec![Topic::ApprovalQueue]);
+    }
+}
@@ -0,0 +1,311 @@
+use super::{dispatch, ErrorCode, ServerState, ServiceError, ServiceResponse};
+
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{Map, Value};
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+
+/// REST flavoured frontend for the same operations the websocket dispatches, every route is
+/// translated into an operation name plus body and handed to `dispatch`
+pub async fn serve(listener: TcpListener, state: ServerState) {
+    let make_service = make_service_fn(move |stream: &AddrStream| {
+        let state = state.clone();
+        let remote = stream.remote_addr();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(request, remote, state.clone()))) }
+    });
+
+    if let Ok(address) = listener.local_addr() {
+        println!("Opening HTTP Server on {} ...", address);
+    }
+
+    let server = match listener.into_std().map(Server::from_tcp) {
+        Ok(Ok(server)) => server,
+        Ok(Err(e)) => {
+            println!("could not open the http server {:?}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("could not open the http server {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = server.serve(make_service).await {
+        println!("http server crashed {:?}", e);
+    }
+}
+
+/// maps method and path onto the operation name used by the websocket protocol
+fn route(method: &Method, path: &[&str], body: Option<Value>, query: Option<Value>) -> Option<(&'static str, Option<Value>)> {
+    let route = match (method, path) {
+        (&Method::POST, ["users"]) => ("user/register", body),
+        (&Method::POST, ["users", "login"]) => ("user/login", body),
+        (&Method::POST, ["users", "login", "totp"]) => ("user/login_totp", body),
+        (&Method::GET, ["users", "session"]) => ("user/session", None),
+        (&Method::POST, ["users", "logout"]) => ("user/logout", None),
+        (&Method::GET, ["users", "sessions"]) => ("user/sessions", query),
+        (&Method::DELETE, ["users", "sessions", id]) => ("user/revoke_session", with_id(None, id)),
+        (&Method::GET, ["users"]) => ("user/list", query),
+        (&Method::PATCH, ["users", id]) => ("user/modify", with_id(body, id)),
+        (&Method::DELETE, ["users", id]) => ("user/delete", with_id(None, id)),
+        (&Method::POST, ["users", "unlock"]) => ("user/unlock", body),
+        (&Method::POST, ["users", "invite"]) => ("user/invite", body),
+        (&Method::GET, ["users", "verify"]) => ("user/verify_email", query),
+        (&Method::POST, ["users", "verify"]) => ("user/verify_email", body),
+        (&Method::POST, ["users", "password", "request_reset"]) => ("user/request_password_reset", body),
+        (&Method::POST, ["users", "password", "reset"]) => ("user/reset_password", body),
+        (&Method::POST, ["users", "verify", "resend"]) => ("user/resend_verification", None),
+        (&Method::POST, ["users", "totp"]) => ("user/totp/enroll", None),
+        (&Method::POST, ["users", "totp", "confirm"]) => ("user/totp/confirm", body),
+        (&Method::POST, ["users", "totp", "disable"]) => ("user/totp/disable", body),
+        (&Method::DELETE, ["users", id, "totp"]) => ("user/totp/disable", with_id(body, id)),
+
+        (&Method::GET, ["roles"]) => ("role/list", None),
+        (&Method::PUT, ["roles", "totp"]) => ("role/require_totp", body),
+
+        (&Method::GET, ["stations"]) => ("station/list", query),
+        (&Method::POST, ["stations"]) => ("station/create", body),
+        (&Method::PATCH, ["stations", id]) => ("station/modify", with_id(body, id)),
+        (&Method::DELETE, ["stations", id]) => ("station/delete", with_id(None, id)),
+        (&Method::POST, ["stations", id, "approve"]) => ("station/approve", with_id(body, id)),
+        (&Method::POST, ["stations", id, "review"]) => ("station/review", with_id(body, id)),
+        (&Method::GET, ["stations", id, "review"]) => ("station/reviews", with_id(None, id)),
+        (&Method::POST, ["stations", id, "token"]) => ("station/generate_token", with_id(body, id)),
+        (&Method::GET, ["stations", id, "token"]) => ("station/tokens", with_id(None, id)),
+        (&Method::DELETE, ["stations", id, "token", "previous"]) => ("station/revoke_previous_token", with_id(None, id)),
+        (&Method::POST, ["stations", id, "verify"]) => ("station/verify_token", with_id(body, id)),
+        (&Method::POST, ["stations", id, "heartbeat"]) => ("station/heartbeat", with_id(body, id)),
+        (&Method::GET, ["stations", id, "heartbeat"]) => ("station/heartbeats", with_id(None, id)),
+        (&Method::POST, ["stations", id, "config"]) => ("station/config", with_id(body, id)),
+
+        (&Method::GET, ["regions"]) => ("region/list", query),
+        (&Method::POST, ["regions"]) => ("region/create", body),
+        (&Method::PATCH, ["regions", id]) => ("region/modify", with_id(body, id)),
+        (&Method::DELETE, ["regions", id]) => ("region/delete", with_id(None, id)),
+
+        (&Method::GET, ["export", "geojson"]) => ("export/geojson", None),
+        (&Method::GET, ["audit"]) => ("audit/list", query),
+
+        _ => return None,
+    };
+
+    Some(route)
+}
+
+/// puts the identifier from the path into the request body, numeric ids (regions) stay numbers
+fn with_id(body: Option<Value>, id: &str) -> Option<Value> {
+    let mut object = match body {
+        Some(Value::Object(object)) => object,
+        _ => Map::new(),
+    };
+
+    object.insert(String::from("id"), parse_value(id));
+    Some(Value::Object(object))
+}
+
+/// path identifiers are either station and user uuids or region numbers
+fn parse_value(raw: &str) -> Value {
+    match raw.parse::<u64>() {
+        Ok(number) => Value::from(number),
+        Err(_) => Value::from(raw),
+    }
+}
+
+/// query strings like `?desired_region=1` become the body of list operations, every value stays
+/// text and the request types parse the ones that are numbers
+fn parse_query(query: Option<&str>) -> Option<Value> {
+    let object: Map<String, Value> = form_urlencoded::parse(query?.as_bytes())
+        .map(|(key, value)| (key.into_owned(), Value::from(value.into_owned())))
+        .collect();
+
+    Some(Value::Object(object))
+}
+
+fn bearer_token(request: &Request<Body>) -> Option<String> {
+    request
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+fn status_code(code: Option<ErrorCode>) -> StatusCode {
+    match code {
+        None => StatusCode::OK,
+        Some(ErrorCode::NotFound) => StatusCode::NOT_FOUND,
+        Some(ErrorCode::Conflict) => StatusCode::CONFLICT,
+        Some(ErrorCode::ForeignKeyViolation) => StatusCode::CONFLICT,
+        Some(ErrorCode::PermissionDenied) => StatusCode::FORBIDDEN,
+        Some(ErrorCode::Unauthenticated) => StatusCode::UNAUTHORIZED,
+        Some(ErrorCode::EmailNotVerified) => StatusCode::FORBIDDEN,
+        Some(ErrorCode::TotpRequired) => StatusCode::FORBIDDEN,
+        Some(ErrorCode::TooManyAttempts) => StatusCode::TOO_MANY_REQUESTS,
+        Some(ErrorCode::Validation) => StatusCode::BAD_REQUEST,
+        Some(ErrorCode::Internal) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// answers that carry a `cache_ttl` (token verification) may be cached by the caller, nothing else
+fn cache_control(response: &ServiceResponse) -> String {
+    match response.data.get("cache_ttl").and_then(Value::as_u64) {
+        Some(ttl) => format!("private, max-age={}", ttl),
+        None => String::from("no-store"),
+    }
+}
+
+fn json_response(response: &ServiceResponse) -> Response<Body> {
+    Response::builder()
+        .status(status_code(response.error.as_ref().map(|error| error.code)))
+        .header(CONTENT_TYPE, "application/json")
+        .header(CACHE_CONTROL, cache_control(response))
+        .body(Body::from(serde_json::to_string(response).unwrap()))
+        .unwrap()
+}
+
+/// map tools expect a plain FeatureCollection, so the export skips the envelope on success
+fn geojson_response(response: &ServiceResponse) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/geo+json")
+        .body(Body::from(serde_json::to_string(&response.data).unwrap()))
+        .unwrap()
+}
+
+fn error_response(operation: &str, error: ServiceError) -> Response<Body> {
+    json_response(&ServiceResponse::new(operation.to_string(), None, Err(error)))
+}
+
+async fn handle(request: Request<Body>, remote: SocketAddr, state: ServerState) -> Result<Response<Body>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let query = parse_query(request.uri().query());
+    let token = bearer_token(&request);
+
+    let bytes = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("could not read http body {:?}", e);
+            return Ok(error_response("", ServiceError::Validation(String::from("could not read body"))));
+        }
+    };
+
+    let body = if bytes.is_empty() {
+        None
+    } else {
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(body) => Some(body),
+            Err(e) => return Ok(error_response("", ServiceError::Validation(format!("body is not json {}", e)))),
+        }
+    };
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let (operation, body) = match route(&method, &segments, body, query) {
+        Some(route) => route,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+
+    println!("HTTP {} {} -> {}", method, path, operation);
+
+    // same as for websocket messages the handlers block on the database
+    let response = tokio::task::spawn_blocking(move || {
+        let mut connection = state.connection(None, Some(remote));
+
+        if let Some(token) = token {
+            if let Err(e) = connection.authenticate(&token) {
+                return ServiceResponse::new(operation.to_string(), None, Err(e));
+            }
+        }
+
+        let result = dispatch(&mut connection, operation, body);
+        ServiceResponse::new(operation.to_string(), None, result)
+    })
+    .await;
+
+    Ok(match response {
+        Ok(response) if response.success && operation == "export/geojson" => geojson_response(&response),
+        Ok(response) => json_response(&response),
+        Err(e) => {
+            println!("http handler crashed {:?}", e);
+            error_response(operation, ServiceError::Internal)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListStationsRequest;
+
+    use serde_json::json;
+
+    #[test]
+    fn query_values_are_percent_decoded_text() {
+        assert_eq!(
+            parse_query(Some("name=ren%61med+station&created_after=2022-05-01T12%3A00%3A00%2B01%3A00&limit=10")),
+            Some(json!({
+                "name": "renamed station",
+                "created_after": "2022-05-01T12:00:00+01:00",
+                "limit": "10",
+            }))
+        );
+        assert_eq!(parse_query(Some("")), Some(json!({})));
+        assert_eq!(parse_query(None), None);
+    }
+
+    #[test]
+    fn station_list_query_decodes_into_its_request() {
+        let query = parse_query(Some("limit=10&approved=true&desired_region=2&order=desc&name=007"));
+        let (operation, body) = route(&Method::GET, &["stations"], None, query).unwrap();
+        assert_eq!(operation, "station/list");
+
+        let request: ListStationsRequest = serde_json::from_value(body.unwrap()).unwrap();
+        assert_eq!(request.options.limit, Some(10));
+        assert_eq!(request.approved, Some(true));
+        assert_eq!(request.desired_region, Some(2));
+        assert_eq!(serde_json::to_value(request.options.order).unwrap(), json!("desc"));
+        // a name that looks like a number stays a name
+        assert_eq!(request.options.name.as_deref(), Some("007"));
+    }
+
+    #[test]
+    fn broken_query_values_are_refused() {
+        let query = parse_query(Some("limit=ten"));
+        assert!(serde_json::from_value::<ListStationsRequest>(query.unwrap()).is_err());
+    }
+
+    #[test]
+    fn path_ids_are_numbers_for_regions_and_text_for_uuids() {
+        let (operation, body) = route(&Method::DELETE, &["regions", "7"], None, None).unwrap();
+        assert_eq!(operation, "region/delete");
+        assert_eq!(body, Some(json!({ "id": 7 })));
+
+        let id = "969cdf1a-0b5e-4c36-8a3e-2d3b0f4f6f10";
+        let (operation, body) = route(&Method::PATCH, &["stations", id], Some(json!({ "name": "x" })), None).unwrap();
+        assert_eq!(operation, "station/modify");
+        assert_eq!(body, Some(json!({ "id": id, "name": "x" })));
+    }
+
+    #[test]
+    fn path_id_replaces_an_id_in_the_body() {
+        let body = with_id(Some(json!({ "id": 1, "name": "x" })), "2");
+        assert_eq!(body, Some(json!({ "id": 2, "name": "x" })));
+    }
+
+    #[test]
+    fn unknown_routes_are_none() {
+        assert!(route(&Method::GET, &["nothing"], None, None).is_none());
+        assert!(route(&Method::PUT, &["stations"], None, None).is_none());
+    }
+}
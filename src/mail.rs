@@ -0,0 +1,137 @@
+use super::structs::{Args, MailTransportKind};
+use super::ServiceError;
+
+use chrono::Utc;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use uuid::Uuid;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// plain text mail to a single recipient
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// how mails leave the server, the handlers block anyway so sending is synchronous
+pub trait MailTransport: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), ServiceError>;
+}
+
+/// shared handle to the configured transport
+pub type Mailer = Arc<dyn MailTransport>;
+
+fn message(from: &Mailbox, mail: &Mail) -> Result<Message, ServiceError> {
+    let to = mail
+        .to
+        .parse::<Mailbox>()
+        .map_err(|_| ServiceError::Validation(String::from("invalid email address")))?;
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(mail.subject.clone())
+        .body(mail.body.clone())
+        .map_err(|e| {
+            println!("could not build mail {:?}", e);
+            ServiceError::Internal
+        })
+}
+
+/// builds the transport selected on the command line
+pub fn from_args(args: &Args) -> Result<Mailer, String> {
+    match args.mail_transport {
+        MailTransportKind::Outbox => Ok(Arc::new(OutboxMailer::new(
+            PathBuf::from(&args.mail_outbox),
+            &args.mail_from,
+        )?)),
+        MailTransportKind::Smtp => {
+            let host = args
+                .smtp_host
+                .as_ref()
+                .ok_or_else(|| String::from("--smtp-host is required for the smtp transport"))?;
+            let credentials = args
+                .smtp_user
+                .clone()
+                .map(|user| (user, env::var("SMTP_PASSWORD").unwrap_or_default()));
+
+            Ok(Arc::new(SmtpMailer::new(host, args.smtp_port, credentials, &args.mail_from)?))
+        }
+    }
+}
+
+/// delivers through a relay with STARTTLS
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, port: u16, credentials: Option<(String, String)>, from: &str) -> Result<SmtpMailer, String> {
+        let from = from
+            .parse::<Mailbox>()
+            .map_err(|e| format!("invalid sender address {}: {}", from, e))?;
+
+        let mut builder = SmtpTransport::starttls_relay(host)
+            .map_err(|e| format!("invalid smtp relay {}: {}", host, e))?
+            .port(port);
+        if let Some((user, password)) = credentials {
+            builder = builder.credentials(Credentials::new(user, password));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+impl MailTransport for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<(), ServiceError> {
+        self.transport.send(&message(&self.from, mail)?).map_err(|e| {
+            println!("could not send mail to {} {:?}", mail.to, e);
+            ServiceError::Internal
+        })?;
+
+        Ok(())
+    }
+}
+
+/// writes every mail as `.eml` file into a directory instead of sending it, for tests and
+/// deployments without a mail server
+pub struct OutboxMailer {
+    directory: PathBuf,
+    from: Mailbox,
+}
+
+impl OutboxMailer {
+    pub fn new(directory: PathBuf, from: &str) -> Result<OutboxMailer, String> {
+        fs::create_dir_all(&directory)
+            .map_err(|e| format!("could not create outbox {}: {}", directory.display(), e))?;
+        let from = from
+            .parse::<Mailbox>()
+            .map_err(|e| format!("invalid sender address {}: {}", from, e))?;
+
+        Ok(OutboxMailer { directory, from })
+    }
+}
+
+impl MailTransport for OutboxMailer {
+    fn send(&self, mail: &Mail) -> Result<(), ServiceError> {
+        let path = self
+            .directory
+            .join(format!("{}-{}.eml", Utc::now().format("%Y%m%dT%H%M%S"), Uuid::new_v4()));
+
+        fs::write(&path, message(&self.from, mail)?.formatted()).map_err(|e| {
+            println!("could not write mail to {} {:?}", path.display(), e);
+            ServiceError::Internal
+        })
+    }
+}
@@ -1,24 +1,44 @@
+mod bootstrap;
 mod database;
 mod endpoints;
+mod error;
+mod events;
+mod http;
+mod mail;
+mod password;
+mod permissions;
+mod session;
 mod structs;
+mod throttle;
+mod token;
+mod totp;
 
-pub use database::{DataBaseConnection, Region, Role, Station, User};
+pub use database::{latest_version, text_or_value, AuditEntry, AuditFilter, DataBaseConnection, DataBasePool, Heartbeat, ListOptions, Page, Polygon, Region, Review, ReviewState, Role, Session, Station, StationFilter, StationTokens, TokenPurpose, User};
 use endpoints::{
     approve_station, create_region, create_station, create_user, list_users, delete_region, delete_station,
     delete_user, generate_token, get_session, list_regions, list_stations, login, modify_region,
-    modify_station, modify_user, ListStationsRequest, ApproveStation, CreateStationRequest, UuidRequest, RegisterUserRequest, LoginRequest, ModifyUserRequest, ModifyRegionRequest, RegionRequest, ModifyStation, IdentifierRequest
+    modify_station, modify_user, verify_token, VerifyTokenRequest, station_tokens, revoke_previous_token, GenerateTokenRequest, heartbeat, list_heartbeats, HeartbeatRequest, station_config, StationConfigRequest, export_geojson, review_station, list_reviews, ReviewStationRequest, list_sessions, logout, resume, revoke_session, request_password_reset, reset_password, RequestPasswordReset, ResetPasswordRequest, verify_email, resend_verification, TokenRequest, unlock_login, UnlockLoginRequest, list_audit, ListAuditRequest, AuditRecord, subscribe, unsubscribe, list_subscriptions, check_totp_policy, login_totp, enroll_totp, confirm_totp, disable_totp, list_role_policies, set_role_policy, create_invite, InviteRequest, TotpLoginRequest, TotpCodeRequest, DisableTotpRequest, RolePolicyRequest, ListStationsRequest, ListSessionsRequest, ResumeRequest, ApproveStation, CreateStationRequest, UuidRequest, RegisterUserRequest, LoginRequest, ModifyUserRequest, ModifyRegionRequest, RegionRequest, ModifyStation, IdentifierRequest
 };
-use structs::Args;
+pub use error::{ErrorCode, ServiceError};
+pub use permissions::Permission;
+use permissions::list_permissions;
+use endpoints::ServiceResponse;
+use events::{EventBus, Topic};
+use mail::Mailer;
+use password::PasswordHashing;
+use session::SessionStore;
+use structs::{Args, Command, RegistrationMode};
+use throttle::LoginThrottle;
 
 use serde::de::DeserializeOwned;
 use clap::Parser;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use tungstenite::accept;
-
-use std::net::TcpListener;
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::accept_async;
+use tungstenite::Message;
 
 /*  TODO:
  *  - admin user (first user creates)
@@ -32,172 +52,547 @@ use std::net::TcpListener;
 struct MessageTemplate {
     operation: String,
     body: Option<serde_json::Value>,
+    /// optional client chosen correlation id, echoed back in the response
+    id: Option<serde_json::Value>,
 }
 
-#[derive(Serialize)]
-pub struct ServiceResponse {
-    success: bool,
-    message: Option<String>
+/// state shared by every connection of the websocket and the http server
+#[derive(Clone)]
+pub struct ServerState {
+    database: DataBasePool,
+    sessions: SessionStore,
+    passwords: PasswordHashing,
+    throttle: LoginThrottle,
+    mailer: Mailer,
+    events: EventBus,
+    verification_lifetime: i64,
+    password_reset_lifetime: i64,
+    totp_issuer: String,
+    registration: RegistrationMode,
+    invite_lifetime: i64,
+    token_cache_ttl: u32,
+    token_grace_period: u32,
+    station_timeout: u32,
+    telegram_endpoints: Vec<String>,
+    public_url: Option<String>,
+}
+
+impl ServerState {
+    /// http requests have no socket, their answer is the http response
+    pub fn connection(&self, socket: Option<UnboundedSender<Message>>, address: Option<SocketAddr>) -> UserConnection {
+        UserConnection {
+            database: self.database.clone(),
+            sessions: self.sessions.clone(),
+            passwords: self.passwords.clone(),
+            throttle: self.throttle.clone(),
+            mailer: self.mailer.clone(),
+            events: self.events.clone(),
+            verification_lifetime: self.verification_lifetime,
+            password_reset_lifetime: self.password_reset_lifetime,
+            totp_issuer: self.totp_issuer.clone(),
+            registration: self.registration,
+            invite_lifetime: self.invite_lifetime,
+            id: uuid::Uuid::new_v4(),
+            token_cache_ttl: self.token_cache_ttl,
+            token_grace_period: self.token_grace_period,
+            station_timeout: self.station_timeout,
+            telegram_endpoints: self.telegram_endpoints.clone(),
+            public_url: self.public_url.clone(),
+            socket,
+            address,
+            user: None,
+            session: None,
+            changes: Vec::new(),
+        }
+    }
 }
 
 pub struct UserConnection {
-    database: Arc<Mutex<DataBaseConnection>>,
-    socket: tungstenite::protocol::WebSocket<std::net::TcpStream>,
+    database: DataBasePool,
+    sessions: SessionStore,
+    passwords: PasswordHashing,
+    throttle: LoginThrottle,
+    mailer: Mailer,
+    events: EventBus,
+    /// hours an email verification code stays valid
+    verification_lifetime: i64,
+    /// minutes a password reset code stays valid
+    password_reset_lifetime: i64,
+    /// shown in authenticator apps next to the account name
+    totp_issuer: String,
+    /// who may use `user/register`
+    registration: RegistrationMode,
+    /// days an invite stays valid
+    invite_lifetime: i64,
+    /// identifies the connection in the subscriptions of `events`
+    id: uuid::Uuid,
+    /// seconds services may cache the answer of `station/verify_token`
+    token_cache_ttl: u32,
+    /// hours a replaced station token stays valid unless the request says otherwise
+    token_grace_period: u32,
+    /// seconds without heartbeat after which a station counts as offline
+    station_timeout: u32,
+    /// written into generated receiver configs
+    telegram_endpoints: Vec<String>,
+    public_url: Option<String>,
+    socket: Option<UnboundedSender<Message>>,
+    /// peer address of the client, recorded in the audit log
+    address: Option<SocketAddr>,
     user: Option<User>,
+    /// the session this connection was authenticated with, logout ends it
+    session: Option<uuid::Uuid>,
+    /// changes of the running request, published once they are committed
+    changes: Vec<events::Change>,
 }
 
-fn call_backend<T: DeserializeOwned>(data: serde_json::Value, function: Box<dyn Fn(&mut UserConnection, T)>, connection: &mut UserConnection) 
-{
-    match serde_json::value::from_value::<T>(data) {
-        Ok(parsed_struct) => {
-            function(connection, parsed_struct);
-        }
-        _ => {
-            let serialized = serde_json::to_string(&ServiceResponse { success: false , message: Some(String::from("decoding failed"))}).unwrap();
-            connection
-                .socket
-                .write_message(tungstenite::Message::Text(serialized))
-                .unwrap();
-        }
-    }
-}
-
-fn process_message(connection: &mut UserConnection, message: &tungstenite::protocol::Message) {
-    let command: String;
-    let raw_body: Option<serde_json::Value>;
-
-    match message {
-        tungstenite::protocol::Message::Text(text) => {
-            let parsed: MessageTemplate;
-            match serde_json::from_str(&text) {
-                Ok(data) => {
-                    parsed = data;
-                }
-                Err(e) => {
-                    println!("user send incorrect message {:?}", e);
-                    let serialized =
-                        serde_json::to_string(&ServiceResponse { success: false, message: Some(String::from("operation entry is missing")) }).unwrap();
-                    connection
-                        .socket
-                        .write_message(tungstenite::Message::Text(serialized))
-                        .unwrap();
-
-                    return;
-                }
+impl UserConnection {
+    /// queues a text frame for the writer task of this connection, if the client already
+    /// hung up the message is dropped
+    pub fn write_message(&mut self, serialized: String) {
+        if let Some(socket) = &self.socket {
+            if socket.send(Message::Text(serialized)).is_err() {
+                println!("client already disconnected dropping response");
             }
-            command = parsed.operation;
-            raw_body = parsed.body;
         }
-        _ => {
-            return;
+    }
+
+    /// logs the connection in as the owner of a session token
+    pub fn authenticate(&mut self, token: &str) -> Result<(), ServiceError> {
+        let mut database = self.database.get()?;
+        let session = self.sessions.resume(&mut database, token)?;
+        self.user = Some(database.query_user_by_id(&session.owner)?);
+        self.session = Some(session.id);
+        Ok(())
+    }
+
+    /// pushes a change to every subscribed websocket connection once the request is done
+    pub fn publish(&mut self, change: events::Change) {
+        self.changes.push(change);
+    }
+}
+
+/// turns the answer of a handler into the `data` field of the response envelope
+fn to_data<R: Serialize>(result: Result<R, ServiceError>) -> Result<serde_json::Value, ServiceError> {
+    result.map(|response| serde_json::to_value(response).unwrap())
+}
+
+fn call_backend<T: DeserializeOwned, R: Serialize>(
+    data: serde_json::Value,
+    function: impl Fn(&mut UserConnection, T) -> Result<R, ServiceError>,
+    connection: &mut UserConnection,
+) -> Result<serde_json::Value, ServiceError> {
+    let parsed_struct = serde_json::value::from_value::<T>(data)
+        .map_err(|e| ServiceError::Validation(format!("decoding failed {}", e)))?;
+
+    to_data(function(connection, parsed_struct))
+}
+
+/// runs an operation and records it in the audit log if it changed something, the change and
+/// its audit entry are committed together
+fn dispatch(connection: &mut UserConnection, operation: &str, body: Option<serde_json::Value>) -> Result<serde_json::Value, ServiceError> {
+    connection.refresh_user()?;
+    check_totp_policy(connection, operation)?;
+    connection.changes.clear();
+
+    if !AuditRecord::audited(connection, operation, body.as_ref()) {
+        let result = route(connection, operation, body);
+        publish_changes(connection);
+        return result;
+    }
+
+    let (user, session) = (connection.user.clone(), connection.session);
+    connection.database.begin()?;
+
+    let audit = AuditRecord::begin(connection, operation, body.as_ref());
+    let result = route(connection, operation, body);
+
+    // a failed operation keeps what it stored, like the counted failures of a login, only a
+    // change that can not be logged is thrown away
+    let (result, commit) = match result {
+        Ok(data) => match audit.finish(connection, &data) {
+            Ok(()) => (Ok(data), true),
+            Err(e) => (Err(e), false),
+        },
+        Err(e) => (Err(e), true),
+    };
+    let committed = match connection.database.end(commit) {
+        Ok(()) => commit,
+        Err(e) => {
+            println!("could not end the transaction of {} {:?}", operation, e);
+            false
         }
+    };
+
+    // nothing of the operation is stored, the connection forgets what it did as well
+    if !committed {
+        connection.user = user;
+        connection.session = session;
+        connection.changes.clear();
+        return Err(ServiceError::Internal);
     }
+    publish_changes(connection);
+    result
+}
+
+fn publish_changes(connection: &mut UserConnection) {
+    for change in connection.changes.drain(..) {
+        connection.events.publish(change);
+    }
+}
+
+/// fields that carry passwords, tokens or codes and never end up in the log
+const SECRET_FIELDS: &[&str] = &["password", "token", "code", "challenge", "invite"];
+
+/// copy of a request body with the secrets blanked out for logging
+fn redacted(body: &serde_json::Value) -> serde_json::Value {
+    match body {
+        serde_json::Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| {
+                let value = if SECRET_FIELDS.contains(&key.as_str()) {
+                    serde_json::Value::from("<redacted>")
+                } else {
+                    redacted(value)
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+        serde_json::Value::Array(items) => items.iter().map(redacted).collect(),
+        other => other.clone(),
+    }
+}
 
+/// routes one operation to its handler in `endpoints`
+fn route(connection: &mut UserConnection, operation: &str, body: Option<serde_json::Value>) -> Result<serde_json::Value, ServiceError> {
     let authenticated = connection.user.is_some();
 
-    println!("command: {}, body: {:?}, authenticated: {}", &command.as_str(), &raw_body, authenticated);
+    println!(
+        "command: {}, body: {:?}, authenticated: {}",
+        operation,
+        body.as_ref().map(redacted),
+        authenticated
+    );
 
-    match (command.as_str(), raw_body, authenticated) {
-        ("user/register", Some(body), false) => {
-            call_backend::<RegisterUserRequest>(body, Box::new(create_user), connection);
-        }
-        ("user/login", Some(body), false) => {
-            call_backend::<LoginRequest>(body, Box::new(login), connection);
-        }
-        ("user/session", None, true) => {
-            get_session(connection);
-        }
-        ("user/delete", Some(body), true) => {
-            call_backend::<UuidRequest>(body, Box::new(delete_user), connection);
-        }
-        ("user/modify", Some(body), true) => {
-            call_backend::<ModifyUserRequest>(body, Box::new(modify_user), connection);
-        }
-        ("user/list", None, true) => {
-            list_users(connection);
-        }
-        ("station/create", Some(body), true) => {
-            call_backend::<CreateStationRequest>(body, Box::new(create_station), connection);
-        }
-        ("station/list", Some(body), _) => {
-            call_backend::<ListStationsRequest>(body, Box::new(list_stations), connection);
-        }
-        ("station/list", None, _) => {
-            list_stations(connection, ListStationsRequest { desired_owner: None, desired_region: None});
+    match (operation, body, authenticated) {
+        ("user/register", Some(body), false) => call_backend::<RegisterUserRequest, _>(body, create_user, connection),
+        ("user/login", Some(body), false) => call_backend::<LoginRequest, _>(body, login, connection),
+        ("user/login_totp", Some(body), false) => call_backend::<TotpLoginRequest, _>(body, login_totp, connection),
+        ("user/session", None, true) => to_data(get_session(connection)),
+        ("user/resume", Some(body), false) => call_backend::<ResumeRequest, _>(body, resume, connection),
+        ("user/logout", None, true) => to_data(logout(connection)),
+        ("user/sessions", Some(body), true) => call_backend::<ListSessionsRequest, _>(body, list_sessions, connection),
+        ("user/sessions", None, true) => to_data(list_sessions(connection, ListSessionsRequest { user: None })),
+        ("user/revoke_session", Some(body), true) => call_backend::<UuidRequest, _>(body, revoke_session, connection),
+        ("user/delete", Some(body), true) => call_backend::<UuidRequest, _>(body, delete_user, connection),
+        ("user/modify", Some(body), true) => call_backend::<ModifyUserRequest, _>(body, modify_user, connection),
+        ("user/verify_email", Some(body), _) => call_backend::<TokenRequest, _>(body, verify_email, connection),
+        ("user/resend_verification", None, true) => to_data(resend_verification(connection)),
+        ("user/request_password_reset", Some(body), _) => call_backend::<RequestPasswordReset, _>(body, request_password_reset, connection),
+        ("user/reset_password", Some(body), _) => call_backend::<ResetPasswordRequest, _>(body, reset_password, connection),
+        ("user/invite", Some(body), true) => call_backend::<InviteRequest, _>(body, create_invite, connection),
+        ("user/invite", None, true) => to_data(create_invite(connection, InviteRequest::default())),
+        ("user/unlock", Some(body), true) => call_backend::<UnlockLoginRequest, _>(body, unlock_login, connection),
+        ("user/permissions", None, true) => to_data(list_permissions(connection)),
+        ("user/totp/enroll", None, true) => to_data(enroll_totp(connection)),
+        ("user/totp/confirm", Some(body), true) => call_backend::<TotpCodeRequest, _>(body, confirm_totp, connection),
+        ("user/totp/disable", Some(body), true) => call_backend::<DisableTotpRequest, _>(body, disable_totp, connection),
+        ("role/list", None, true) => to_data(list_role_policies(connection)),
+        ("role/require_totp", Some(body), true) => call_backend::<RolePolicyRequest, _>(body, set_role_policy, connection),
+        ("user/list", Some(body), true) => call_backend::<ListOptions, _>(body, list_users, connection),
+        ("user/list", None, true) => to_data(list_users(connection, ListOptions::default())),
+        ("station/create", Some(body), true) => call_backend::<CreateStationRequest, _>(body, create_station, connection),
+        ("station/list", Some(body), _) => call_backend::<ListStationsRequest, _>(body, list_stations, connection),
+        ("station/list", None, _) => to_data(list_stations(connection, ListStationsRequest::default())),
+        ("station/delete", Some(body), true) => call_backend::<UuidRequest, _>(body, delete_station, connection),
+        ("station/modify", Some(body), true) => call_backend::<ModifyStation, _>(body, modify_station, connection),
+        ("station/approve", Some(body), true) => call_backend::<ApproveStation, _>(body, approve_station, connection),
+        ("station/review", Some(body), true) => call_backend::<ReviewStationRequest, _>(body, review_station, connection),
+        ("station/reviews", Some(body), true) => call_backend::<UuidRequest, _>(body, list_reviews, connection),
+        ("station/generate_token", Some(body), true) => call_backend::<GenerateTokenRequest, _>(body, generate_token, connection),
+        ("station/tokens", Some(body), true) => call_backend::<UuidRequest, _>(body, station_tokens, connection),
+        ("station/revoke_previous_token", Some(body), true) => call_backend::<UuidRequest, _>(body, revoke_previous_token, connection),
+        ("station/heartbeat", Some(body), _) => call_backend::<HeartbeatRequest, _>(body, heartbeat, connection),
+        ("station/heartbeats", Some(body), true) => call_backend::<UuidRequest, _>(body, list_heartbeats, connection),
+        ("station/config", Some(body), true) => call_backend::<StationConfigRequest, _>(body, station_config, connection),
+        ("station/verify_token", Some(body), true) => call_backend::<VerifyTokenRequest, _>(body, verify_token, connection),
+        ("region/create", Some(body), true) => call_backend::<RegionRequest, _>(body, create_region, connection),
+        ("region/delete", Some(body), true) => call_backend::<IdentifierRequest, _>(body, delete_region, connection),
+        ("region/modify", Some(body), true) => call_backend::<ModifyRegionRequest, _>(body, modify_region, connection),
+        ("region/list", Some(body), _) => call_backend::<ListOptions, _>(body, list_regions, connection),
+        ("region/list", None, _) => to_data(list_regions(connection, ListOptions::default())),
+        ("export/geojson", None, _) => export_geojson(connection),
+        ("audit/list", Some(body), true) => call_backend::<ListAuditRequest, _>(body, list_audit, connection),
+        ("audit/list", None, true) => to_data(list_audit(connection, ListAuditRequest::default())),
+        ("subscribe", Some(body), true) => call_backend::<Topic, _>(body, subscribe, connection),
+        ("unsubscribe", Some(body), true) => call_backend::<Topic, _>(body, unsubscribe, connection),
+        ("subscriptions", None, true) => to_data(list_subscriptions(connection)),
+        (&_, _, _) => {
+            println!("user send incorrect operation or unathenticated");
+            Err(ServiceError::Validation(String::from("unkown endpoint check if the operation is spelled correctly or if you are authenticated.")))
         }
-        ("station/delete", Some(body), true) => {
-            call_backend::<UuidRequest>(body, Box::new(delete_station), connection);
+    }
+}
+
+fn process_message(connection: &mut UserConnection, message: &Message) {
+    let text = match message {
+        Message::Text(text) => text,
+        _ => {
+            return;
         }
-        ("station/modify", Some(body), true) => {
-            call_backend::<ModifyStation>(body, Box::new(modify_station), connection);
+    };
+
+    // the id is picked out first so even malformed requests can be answered with it
+    let raw: serde_json::Value = serde_json::from_str(text).unwrap_or(serde_json::Value::Null);
+    let id = raw.get("id").cloned();
+
+    let response = match serde_json::from_value::<MessageTemplate>(raw) {
+        Ok(parsed) => {
+            let result = dispatch(connection, &parsed.operation, parsed.body);
+            ServiceResponse::new(parsed.operation, parsed.id, result)
         }
-        ("station/approve", Some(body), true) => {
-            call_backend::<ApproveStation>(body, Box::new(approve_station), connection);
+        Err(e) => {
+            println!("user send incorrect message {:?}", e);
+            ServiceResponse::new(
+                String::new(),
+                id,
+                Err(ServiceError::Validation(String::from("operation entry is missing"))),
+            )
         }
-        ("station/generate_token", Some(body), true) => {
-            call_backend::<UuidRequest>(body, Box::new(generate_token), connection);
+    };
+
+    connection.write_message(serde_json::to_string(&response).unwrap());
+}
+
+async fn listen(stream: TcpStream, address: SocketAddr, state: ServerState) {
+    let websocket = match accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            println!("websocket handshake failed {:?}", e);
+            return;
         }
-        ("region/create", Some(body), true) => {
-            call_backend::<RegionRequest>(body, Box::new(create_region), connection);
+    };
+
+    println!("New Connection!");
+    let (mut outgoing, mut incoming) = websocket.split();
+    let (sender, mut receiver) = unbounded_channel::<Message>();
+
+    // all responses go through this task so blocking handlers never touch the socket
+    let writer = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if outgoing.send(message).await.is_err() {
+                break;
+            }
         }
-        ("region/delete", Some(body), true) => {
-            call_backend::<IdentifierRequest>(body, Box::new(delete_region), connection);
+        let _ = outgoing.close().await;
+    });
+
+    let mut connection = state.connection(Some(sender), Some(address));
+    let connection_id = connection.id;
+
+    while let Some(received) = incoming.next().await {
+        let message = match received {
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(message) => message,
+        };
+
+        // the database layer is blocking so the handlers run on the blocking thread pool
+        connection = match tokio::task::spawn_blocking(move || {
+            process_message(&mut connection, &message);
+            connection
+        })
+        .await
+        {
+            Ok(connection) => connection,
+            Err(e) => {
+                println!("handler crashed closing connection {:?}", e);
+                break;
+            }
+        };
+    }
+
+    println!("Connection closed!");
+    state.events.disconnect(&connection_id);
+    writer.abort();
+}
+
+/// brings the schema up to date, exits the process if the database cannot be used by this binary
+fn migrate(database: &DataBasePool, status_only: bool) {
+    let mut connection = database.get().unwrap();
+
+    let result = if status_only {
+        connection.schema_version()
+    } else {
+        connection.migrate()
+    };
+
+    match result {
+        Ok(version) => {
+            println!("Database schema at version {} (latest known {})", version, latest_version());
         }
-        ("region/modify", Some(body), true) => {
-            call_backend::<ModifyRegionRequest>(body, Box::new(modify_region), connection);
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
         }
-        ("region/list", None, _) => {
-            list_regions(connection);
+    }
+}
+
+#[tokio::main]
+async fn serve(args: Args, current_run: DataBasePool, passwords: PasswordHashing, mailer: Mailer) {
+    let (host, port, http_port) = (args.host, args.port, args.http_port);
+    let state = ServerState {
+        database: current_run,
+        sessions: SessionStore::new(args.session_lifetime),
+        passwords,
+        throttle: LoginThrottle::new(
+            args.login_free_attempts,
+            args.login_account_limit,
+            args.login_address_limit,
+            args.login_lockout,
+        ),
+        mailer,
+        events: EventBus::default(),
+        verification_lifetime: args.verification_lifetime,
+        password_reset_lifetime: args.password_reset_lifetime,
+        totp_issuer: args.totp_issuer,
+        registration: args.registration,
+        invite_lifetime: args.invite_lifetime,
+        token_cache_ttl: args.token_cache_ttl,
+        token_grace_period: args.token_grace_period,
+        station_timeout: args.station_timeout,
+        telegram_endpoints: args.telegram_endpoint,
+        public_url: args.public_url,
+    };
+
+    // resolved the same way as the websocket address so host names work for both
+    match TcpListener::bind(format!("{}:{}", host, http_port)).await {
+        Ok(listener) => {
+            tokio::spawn(http::serve(listener, state.clone()));
         }
-        (&_, _, _) => {
-            println!("user send incorrect operation or unathenticated");
-            let serialized =
-                serde_json::to_string(&ServiceResponse { success: false, message: Some(String::from("unkown endpoint check if the operation is spelled correctly or if you are authenticated.")) }).unwrap();
-            connection
-                .socket
-                .write_message(tungstenite::Message::Text(serialized))
-                .unwrap();
+        Err(e) => {
+            println!("could not open the http server on {}:{} {}", host, http_port, e);
+            std::process::exit(1);
         }
     }
-}
 
-fn listen(mut connection: UserConnection) {
+    println!("Listening on: {}:{}", host, port);
+    println!("Opening Websocket Sever ...");
+    let server = TcpListener::bind(format!("{}:{}", host, port)).await.unwrap();
     loop {
-        match connection.socket.read_message() {
-            Ok(message) => {
-                println!("Received Message {:?} !", &message);
-                process_message(&mut connection, &message);
+        match server.accept().await {
+            Ok((stream, address)) => {
+                tokio::spawn(listen(stream, address, state.clone()));
+            }
+            Err(e) => {
+                println!("could not accept connection {:?}", e);
             }
-            _ => {}
         }
     }
 }
 
-//#[tokio::main]
+// the postgres client blocks internally so the pool is set up before the async runtime exists
 fn main() {
     let args = Args::parse();
 
-    let host = args.host.as_str();
-    let port = args.port;
-    let current_run = Arc::new(Mutex::new(DataBaseConnection::new()));
+    let passwords = match PasswordHashing::new(args.argon2_memory, args.argon2_iterations, args.argon2_parallelism) {
+        Ok(passwords) => passwords,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    println!("Listening on: {}:{}", host, port);
-    println!("Opening Websocket Sever ...");
-    let server = TcpListener::bind(format!("{}:{}", host, port)).unwrap();
-    for stream in server.incoming() {
-        let current_run_clone = current_run.clone();
-        thread::spawn(move || {
-            match accept(stream.unwrap()) {
-                Ok(websocket) => {
-                    println!("New Connection!");
-                    listen(UserConnection {
-                        database: current_run_clone,
-                        socket: websocket,
-                        user: None,
-                    });
-                }
-                _ => {}
-            };
+    let current_run = DataBasePool::new(args.pool_size);
+
+    if let Some(Command::Migrate { status }) = &args.command {
+        let status = *status;
+        migrate(&current_run, status);
+        return;
+    }
+
+    migrate(&current_run, false);
+
+    if let Some(Command::CreateAdmin { name, email }) = &args.command {
+        let created = bootstrap::admin_password()
+            .and_then(|password| bootstrap::create_admin(&current_run, &passwords, name, email, &password));
+        match created {
+            Ok(id) => println!("created administrator {} ({})", name, id),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Err(e) = bootstrap::from_env(&current_run, &passwords) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+
+    // only the server sends mails, the subcommands neither need the config nor the outbox
+    let mailer = match mail::from_args(&args) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    serve(args, current_run, passwords, mailer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn redacted_blanks_out_every_secret_field() {
+        let body = json!({
+            "name": "ann",
+            "password": "hunter2",
+            "token": "abc",
+            "code": "123456",
+            "challenge": "def",
+            "invite": "ghi",
         });
+
+        assert_eq!(
+            redacted(&body),
+            json!({
+                "name": "ann",
+                "password": "<redacted>",
+                "token": "<redacted>",
+                "code": "<redacted>",
+                "challenge": "<redacted>",
+                "invite": "<redacted>",
+            })
+        );
+    }
+
+    #[test]
+    fn redacted_follows_nested_objects_and_arrays() {
+        let body = json!({
+            "user": { "name": "ann", "password": { "old": "a", "new": "b" } },
+            "stations": [{ "id": 1, "token": "abc" }, [{ "code": 123456 }], "token"],
+            "count": 2,
+        });
+
+        assert_eq!(
+            redacted(&body),
+            json!({
+                "user": { "name": "ann", "password": "<redacted>" },
+                "stations": [{ "id": 1, "token": "<redacted>" }, [{ "code": "<redacted>" }], "token"],
+                "count": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn redacted_keeps_bodies_without_secrets() {
+        for body in [json!(null), json!("password"), json!([1, 2]), json!({ "id": "x", "tokens": 2 })] {
+            assert_eq!(redacted(&body), body);
+        }
     }
 }
@@ -0,0 +1,155 @@
+use super::ServiceError;
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::Pbkdf2;
+
+/// outcome of checking a password against the stored hash
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// the password matches but was hashed with an older scheme or other parameters
+    /// and should be replaced with a fresh hash
+    Outdated,
+}
+
+/// hashes new passwords with argon2id and a random salt per password, hashes of the old
+/// pbkdf2 scheme are still accepted so existing users can log in and get upgraded
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: Params,
+    /// hash of a random password, checked for names without account so those take as long
+    dummy: String,
+}
+
+impl PasswordHashing {
+    /// memory is given in KiB, the values are checked against the limits of argon2
+    pub fn new(memory: u32, iterations: u32, parallelism: u32) -> Result<PasswordHashing, String> {
+        let params = Params::new(memory, iterations, parallelism, None)
+            .map_err(|e| format!("invalid argon2 parameters: {}", e))?;
+
+        let mut hashing = PasswordHashing {
+            params,
+            dummy: String::new(),
+        };
+        hashing.dummy = hashing
+            .hash(SaltString::generate(&mut OsRng).as_str())
+            .map_err(|_| String::from("could not hash the dummy password"))?;
+
+        Ok(hashing)
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, ServiceError> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| {
+                println!("could not hash password {:?}", e);
+                ServiceError::Internal
+            })
+    }
+
+    /// burns the same time as `verify` without an account to check against
+    pub fn verify_dummy(&self, password: &str) -> Verification {
+        self.verify(password, &self.dummy);
+        Verification::Invalid
+    }
+
+    pub fn verify(&self, password: &str, stored: &str) -> Verification {
+        let hash = match PasswordHash::new(stored) {
+            Ok(hash) => hash,
+            Err(e) => {
+                println!("stored password hash is malformed {:?}", e);
+                return Verification::Invalid;
+            }
+        };
+
+        let argon2 = self.argon2();
+        if hash
+            .verify_password(&[&argon2, &Pbkdf2], password.as_bytes())
+            .is_err()
+        {
+            return Verification::Invalid;
+        }
+
+        let current = hash.algorithm == Algorithm::Argon2id.ident()
+            && Params::try_from(&hash).is_ok_and(|params| {
+                params.m_cost() == self.params.m_cost()
+                    && params.t_cost() == self.params.t_cost()
+                    && params.p_cost() == self.params.p_cost()
+            });
+
+        if current {
+            Verification::Valid
+        } else {
+            Verification::Outdated
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `hunter2` as the pbkdf2 0.10 scheme before argon2 stored it, salted with
+    /// `SaltString::b64_encode(b"clicky bunty salt")` and the default 10000 rounds
+    const PBKDF2_HASH: &str = "$pbkdf2-sha256$i=10000,l=32$Y2xpY2t5IGJ1bnR5IHNhbHQ$ijsT2ny6BV2BroMBTo6nrtSAIugO5wUS/m0AEvCwmx8";
+
+    /// the smallest parameters argon2 accepts, the tests only check the outcome
+    fn hashing() -> PasswordHashing {
+        PasswordHashing::new(8, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn fresh_hashes_are_valid() {
+        let hashing = hashing();
+        let hash = hashing.hash("hunter2").unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(hashing.verify("hunter2", &hash), Verification::Valid);
+        assert_eq!(hashing.verify("hunter3", &hash), Verification::Invalid);
+    }
+
+    #[test]
+    fn every_hash_gets_its_own_salt() {
+        let hashing = hashing();
+        assert_ne!(hashing.hash("hunter2").unwrap(), hashing.hash("hunter2").unwrap());
+    }
+
+    #[test]
+    fn pbkdf2_hashes_still_verify_and_are_outdated() {
+        let hashing = hashing();
+        assert_eq!(hashing.verify("hunter2", PBKDF2_HASH), Verification::Outdated);
+        assert_eq!(hashing.verify("hunter3", PBKDF2_HASH), Verification::Invalid);
+    }
+
+    #[test]
+    fn other_argon2_parameters_are_outdated() {
+        let old = PasswordHashing::new(16, 2, 1).unwrap();
+        let hash = old.hash("hunter2").unwrap();
+
+        assert_eq!(old.verify("hunter2", &hash), Verification::Valid);
+        assert_eq!(hashing().verify("hunter2", &hash), Verification::Outdated);
+        assert_eq!(hashing().verify("hunter3", &hash), Verification::Invalid);
+    }
+
+    #[test]
+    fn malformed_hashes_are_invalid() {
+        let hashing = hashing();
+        for stored in ["", "hunter2", "$argon2id$broken", "$pbkdf2-sha256$i=10000,l=32$Y2xpY2t5$"] {
+            assert_eq!(hashing.verify("hunter2", stored), Verification::Invalid, "{}", stored);
+        }
+    }
+
+    #[test]
+    fn dummy_is_invalid() {
+        assert_eq!(hashing().verify_dummy("hunter2"), Verification::Invalid);
+    }
+}
@@ -0,0 +1,150 @@
+use super::{DataBaseConnection, Role, ServiceError, User, UserConnection};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// something a role allows beyond acting on the own account and stations
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// review stations, settle location reviews and watch the approval queue
+    ApproveStations,
+    /// modify, delete and configure stations of other users
+    ManageStations,
+    /// create, modify and delete regions
+    ManageRegions,
+    /// list, modify and delete other users, change roles and revoke their sessions
+    ManageUsers,
+    /// read `audit/list`
+    ViewAuditLog,
+    /// check station tokens with `station/verify_token`
+    VerifyTokens,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+
+        match self {
+            Role::Administrator => &[
+                ApproveStations,
+                ManageStations,
+                ManageRegions,
+                ManageUsers,
+                ViewAuditLog,
+                VerifyTokens,
+            ],
+            Role::Moderator => &[ApproveStations, ManageStations],
+            Role::Service => &[VerifyTokens],
+            Role::User => &[],
+        }
+    }
+}
+
+impl User {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.permissions().contains(&permission)
+    }
+}
+
+/// what `user/permissions` answers
+#[derive(Serialize, Debug)]
+pub struct PermissionsResponse {
+    pub role: Role,
+    pub permissions: &'static [Permission],
+    pub totp_enabled: bool,
+    /// recovery codes that are still unused, running low is a reason to enroll again
+    pub recovery_codes: i64,
+}
+
+impl UserConnection {
+    /// reloads the logged in user so role changes, deleted accounts and revoked sessions apply
+    /// to open connections right away
+    pub fn refresh_user(&mut self) -> Result<(), ServiceError> {
+        let id = match &self.user {
+            Some(user) => user.id,
+            None => return Ok(()),
+        };
+
+        let mut database = self.database.get()?;
+
+        // a revoked session, e.g. after a password reset, ends the login of the connection too
+        if let Some(session) = self.session {
+            match database.query_session(&session) {
+                Ok(session) if session.expires_at > Utc::now() => {}
+                Ok(_) | Err(ServiceError::NotFound(_)) => {
+                    self.forget_login();
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        match database.query_user_by_id(&id) {
+            Ok(user) => {
+                self.events.set_role(&user.id, &user.role);
+                self.user = Some(user);
+            }
+            Err(ServiceError::NotFound(_)) => self.forget_login(),
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+
+    fn forget_login(&mut self) {
+        self.user = None;
+        self.session = None;
+        self.events.disconnect(&self.id);
+    }
+
+    /// whether the logged in user has the permission, false without login
+    pub fn may(&self, permission: Permission) -> bool {
+        self.user.as_ref().is_some_and(|user| user.can(permission))
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<(), ServiceError> {
+        if self.may(permission) {
+            Ok(())
+        } else {
+            Err(ServiceError::PermissionDenied)
+        }
+    }
+
+    /// users may always act on their own account, on other accounts only with the permission
+    pub fn require_self_or(&self, user: &Uuid, permission: Permission) -> Result<(), ServiceError> {
+        if self.user.as_ref().is_some_and(|current| current.id == *user) {
+            Ok(())
+        } else {
+            self.require(permission)
+        }
+    }
+
+    /// owners may always act on their own station, on other stations only with the permission
+    pub fn require_station_owner_or(
+        &self,
+        database: &mut DataBaseConnection,
+        station: &Uuid,
+        permission: Permission,
+    ) -> Result<(), ServiceError> {
+        if self.may(permission) {
+            return Ok(());
+        }
+
+        let owner = database.query_station(station)?.owner;
+        self.require_self_or(&owner, permission)
+    }
+}
+
+pub fn list_permissions(connection: &mut UserConnection) -> Result<PermissionsResponse, ServiceError> {
+    let user = connection.user.as_ref().unwrap();
+    let recovery_codes = connection.database.get()?.count_recovery_codes(&user.id)?;
+
+    Ok(PermissionsResponse {
+        role: user.role.clone(),
+        permissions: user.role.permissions(),
+        totp_enabled: user.totp_enabled,
+        recovery_codes,
+    })
+}
@@ -0,0 +1,42 @@
+use super::token;
+use super::{DataBaseConnection, ServiceError, Session};
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+/// hands out persistent session tokens on login and resolves them again on resume or for
+/// bearer authentication, only the hash of a token is kept in the database
+#[derive(Clone)]
+pub struct SessionStore {
+    lifetime: Duration,
+}
+
+impl SessionStore {
+    pub fn new(lifetime_hours: i64) -> SessionStore {
+        SessionStore {
+            lifetime: Duration::hours(lifetime_hours),
+        }
+    }
+
+    pub fn create(&self, database: &mut DataBaseConnection, owner: Uuid) -> Result<(Session, String), ServiceError> {
+        // piggyback the cleanup on logins so expired sessions do not pile up
+        database.delete_expired_sessions()?;
+
+        let token = token::generate(64);
+        let now = Utc::now();
+        let session = Session {
+            id: Uuid::new_v4(),
+            owner,
+            created_at: now,
+            expires_at: now + self.lifetime,
+            last_used: now,
+        };
+
+        database.create_session(&session, &token::hash(&token))?;
+        Ok((session, token))
+    }
+
+    pub fn resume(&self, database: &mut DataBaseConnection, token: &str) -> Result<Session, ServiceError> {
+        database.use_session(&token::hash(token))
+    }
+}
@@ -1,13 +1,7 @@
 extern crate clap;
 extern crate derive_builder;
 
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Response {
-    pub success: bool,
-}
+use clap::{ArgEnum, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[clap(name = "dump-dvb telegram collection sink")]
@@ -20,4 +14,144 @@ pub struct Args {
 
     #[clap(short, long, default_value_t = 8070)]
     pub port: u16,
+
+    /// port of the REST api, served on the same host as the websocket
+    #[clap(long, default_value_t = 8071)]
+    pub http_port: u16,
+
+    /// maximum number of open postgres connections shared by all clients
+    #[clap(long, default_value_t = 16)]
+    pub pool_size: u32,
+
+    /// hours a login session stays valid
+    #[clap(long, default_value_t = 720)]
+    pub session_lifetime: i64,
+
+    /// memory cost of argon2id password hashes in KiB
+    #[clap(long, default_value_t = 19456)]
+    pub argon2_memory: u32,
+
+    /// number of argon2id passes over the memory
+    #[clap(long, default_value_t = 2)]
+    pub argon2_iterations: u32,
+
+    /// degree of parallelism of argon2id
+    #[clap(long, default_value_t = 1)]
+    pub argon2_parallelism: u32,
+
+    /// failed logins per account and address that are answered without delay
+    #[clap(long, default_value_t = 3)]
+    pub login_free_attempts: u32,
+
+    /// failed logins after which an account is locked for the full lockout
+    #[clap(long, default_value_t = 10)]
+    pub login_account_limit: u32,
+
+    /// failed logins after which a client address is locked for the full lockout
+    #[clap(long, default_value_t = 50)]
+    pub login_address_limit: u32,
+
+    /// minutes a lockout lasts, failures older than this are forgotten
+    #[clap(long, default_value_t = 15)]
+    pub login_lockout: i64,
+
+    /// seconds services may cache the result of a station token verification
+    #[clap(long, default_value_t = 60)]
+    pub token_cache_ttl: u32,
+
+    /// hours a rotated station token stays valid next to its replacement
+    #[clap(long, default_value_t = 24)]
+    pub token_grace_period: u32,
+
+    /// seconds without heartbeat after which a station is reported as offline
+    #[clap(long, default_value_t = 300)]
+    pub station_timeout: u32,
+
+    /// data sink the receivers send their telegrams to, written into generated station configs,
+    /// can be given multiple times
+    #[clap(long)]
+    pub telegram_endpoint: Vec<String>,
+
+    /// base url under which the REST api is reachable from the receivers
+    #[clap(long)]
+    pub public_url: Option<String>,
+
+    /// how mails with verification codes are delivered
+    #[clap(long, arg_enum, default_value = "outbox")]
+    pub mail_transport: MailTransportKind,
+
+    /// sender address of all mails
+    #[clap(long, default_value_t = String::from("noreply@localhost"))]
+    pub mail_from: String,
+
+    /// directory the outbox transport writes mails to
+    #[clap(long, default_value_t = String::from("outbox"))]
+    pub mail_outbox: String,
+
+    /// relay of the smtp transport, the password is read from SMTP_PASSWORD
+    #[clap(long)]
+    pub smtp_host: Option<String>,
+
+    #[clap(long, default_value_t = 587)]
+    pub smtp_port: u16,
+
+    #[clap(long)]
+    pub smtp_user: Option<String>,
+
+    /// hours an email verification code stays valid
+    #[clap(long, default_value_t = 48)]
+    pub verification_lifetime: i64,
+
+    /// minutes a password reset code stays valid
+    #[clap(long, default_value_t = 60)]
+    pub password_reset_lifetime: i64,
+
+    /// name authenticator apps show next to the account for two-factor codes
+    #[clap(long, default_value_t = String::from("dump-dvb"))]
+    pub totp_issuer: String,
+
+    /// who may create an account with `user/register`
+    #[clap(long, arg_enum, default_value = "open")]
+    pub registration: RegistrationMode,
+
+    /// days an invite for `--registration invite-only` stays valid
+    #[clap(long, default_value_t = 7)]
+    pub invite_lifetime: i64,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MailTransportKind {
+    Smtp,
+    Outbox,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationMode {
+    /// everybody can register
+    Open,
+    /// registering needs an invite created by a user manager
+    InviteOnly,
+    /// no new accounts except through `create-admin`
+    Closed,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// applies all pending database migrations and exits
+    Migrate {
+        /// only print the current and the latest known schema version
+        #[clap(long)]
+        status: bool,
+    },
+    /// creates an administrator account and exits, the password is read from ADMIN_PASSWORD or
+    /// from the first line of stdin
+    CreateAdmin {
+        #[clap(long)]
+        name: String,
+        #[clap(long)]
+        email: String,
+    },
 }
@@ -0,0 +1,147 @@
+use super::{DataBaseConnection, ServiceError};
+
+use chrono::{Duration, Utc};
+
+use std::net::IpAddr;
+
+/// counts failed logins per account name and per client address, after a few free attempts
+/// every further failure locks the key for twice as long as the one before until the full
+/// lockout is reached, names that do not exist are counted the same way as real accounts
+#[derive(Clone)]
+pub struct LoginThrottle {
+    free_attempts: u32,
+    account_limit: u32,
+    address_limit: u32,
+    lockout: Duration,
+}
+
+pub fn account_key(name: &str) -> String {
+    format!("account:{}", name)
+}
+
+pub fn address_key(address: &IpAddr) -> String {
+    format!("address:{}", address)
+}
+
+/// password reset requests are counted apart from logins so neither locks the other out
+fn reset_keys(email: &str, address: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![format!("reset:{}", email.to_lowercase())];
+    keys.extend(address.map(|address| format!("reset_address:{}", address)));
+    keys
+}
+
+fn keys(name: &str, address: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![account_key(name)];
+    keys.extend(address.as_ref().map(address_key));
+    keys
+}
+
+impl LoginThrottle {
+    pub fn new(free_attempts: u32, account_limit: u32, address_limit: u32, lockout_minutes: i64) -> LoginThrottle {
+        LoginThrottle {
+            free_attempts,
+            account_limit,
+            address_limit,
+            lockout: Duration::minutes(lockout_minutes),
+        }
+    }
+
+    /// fails while the account or the address is locked, the password is not even looked at then
+    pub fn check(&self, database: &mut DataBaseConnection, name: &str, address: Option<IpAddr>) -> Result<(), ServiceError> {
+        match database.login_locked_until(&keys(name, address))? {
+            Some(until) => Err(ServiceError::TooManyAttempts(until)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn failed(&self, database: &mut DataBaseConnection, name: &str, address: Option<IpAddr>) -> Result<(), ServiceError> {
+        self.count(database, &account_key(name), self.account_limit)?;
+        if let Some(address) = address {
+            self.count(database, &address_key(&address), self.address_limit)?;
+        }
+
+        Ok(())
+    }
+
+    /// every password reset request counts, the mails go to real people, answers
+    /// `too_many_attempts` for an address or client that asked too often
+    pub fn reset_requested(&self, database: &mut DataBaseConnection, email: &str, address: Option<IpAddr>) -> Result<(), ServiceError> {
+        let keys = reset_keys(email, address);
+        if let Some(until) = database.login_locked_until(&keys)? {
+            return Err(ServiceError::TooManyAttempts(until));
+        }
+
+        self.count(database, &keys[0], self.account_limit)?;
+        if let Some(key) = keys.get(1) {
+            self.count(database, key, self.address_limit)?;
+        }
+
+        Ok(())
+    }
+
+    fn count(&self, database: &mut DataBaseConnection, key: &str, limit: u32) -> Result<(), ServiceError> {
+        let failures = database.record_login_failure(key, self.lockout.num_seconds() as f64)?;
+        if let Some(delay) = self.delay(failures as u32, limit) {
+            database.lock_login(key, &(Utc::now() + delay))?;
+        }
+
+        Ok(())
+    }
+
+    /// a successful login forgets the failures of the account and the address
+    pub fn succeeded(&self, database: &mut DataBaseConnection, name: &str, address: Option<IpAddr>) -> Result<(), ServiceError> {
+        for key in keys(name, address) {
+            database.clear_login_failures(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn delay(&self, failures: u32, limit: u32) -> Option<Duration> {
+        if failures >= limit {
+            return Some(self.lockout);
+        }
+
+        let backoff = failures.checked_sub(self.free_attempts + 1)?;
+        let seconds = 1i64 << backoff.min(40);
+        Some(Duration::seconds(seconds.min(self.lockout.num_seconds())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_attempts_are_not_delayed() {
+        let throttle = LoginThrottle::new(3, 10, 50, 15);
+        for failures in 0..=3 {
+            assert_eq!(throttle.delay(failures, 10), None, "{} failures", failures);
+        }
+    }
+
+    #[test]
+    fn delay_doubles_after_the_free_attempts() {
+        let throttle = LoginThrottle::new(3, 10, 50, 15);
+        assert_eq!(throttle.delay(4, 10), Some(Duration::seconds(1)));
+        assert_eq!(throttle.delay(5, 10), Some(Duration::seconds(2)));
+        assert_eq!(throttle.delay(6, 10), Some(Duration::seconds(4)));
+        assert_eq!(throttle.delay(9, 10), Some(Duration::seconds(32)));
+    }
+
+    #[test]
+    fn limit_locks_for_the_full_lockout() {
+        let throttle = LoginThrottle::new(3, 10, 50, 15);
+        assert_eq!(throttle.delay(10, 10), Some(Duration::minutes(15)));
+        assert_eq!(throttle.delay(11, 10), Some(Duration::minutes(15)));
+        assert_eq!(throttle.delay(0, 0), Some(Duration::minutes(15)));
+    }
+
+    #[test]
+    fn delay_never_exceeds_the_lockout() {
+        let throttle = LoginThrottle::new(0, u32::MAX, u32::MAX, 1);
+        assert_eq!(throttle.delay(7, u32::MAX), Some(Duration::seconds(60)));
+        assert_eq!(throttle.delay(1000, u32::MAX), Some(Duration::seconds(60)));
+        assert_eq!(throttle.delay(u32::MAX - 1, u32::MAX), Some(Duration::seconds(60)));
+    }
+}
@@ -0,0 +1,25 @@
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// random alphanumeric secret used for station and session tokens
+pub fn generate(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// tokens carry enough entropy that a plain sha256 is sufficient, only this is stored
+pub fn hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// checks a presented token against a stored hash without leaking timing information
+pub fn verify(token: &str, token_hash: &str) -> bool {
+    hash(token).as_bytes().ct_eq(token_hash.as_bytes()).into()
+}
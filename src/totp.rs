@@ -0,0 +1,149 @@
+use base32::Alphabet;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+/// seconds one code stays the current one
+const PERIOD: i64 = 30;
+const DIGITS: u32 = 6;
+/// steps before and after the current one that are accepted as well, for phones with a drifting clock
+const WINDOW: i64 = 1;
+
+const ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// random 160 bit key as base32, the form authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32::encode(ALPHABET, &secret)
+}
+
+/// HOTP value of RFC 4226 for the counter, zero padded to `digits`
+fn code(key: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+
+    format!("{:0width$}", value % 10u32.pow(digits), width = digits as usize)
+}
+
+/// time step of RFC 6238 the code matches, steps up to `last_step` were used already and are
+/// refused so every code works only once
+pub fn verify(secret: &str, input: &str, last_step: Option<i64>) -> Option<i64> {
+    verify_at(secret, input, last_step, Utc::now().timestamp())
+}
+
+fn verify_at(secret: &str, input: &str, last_step: Option<i64>, now: i64) -> Option<i64> {
+    let key = base32::decode(ALPHABET, secret)?;
+    let input = input.trim();
+    if input.len() != DIGITS as usize {
+        return None;
+    }
+
+    let current = now / PERIOD;
+    (current - WINDOW..=current + WINDOW)
+        .filter(|step| last_step.map_or(true, |last| *step > last))
+        .find(|step| bool::from(code(&key, *step as u64, DIGITS).as_bytes().ct_eq(input.as_bytes())))
+}
+
+fn escape(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// `otpauth://` link for the QR code of authenticator apps
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        escape(issuer),
+        escape(account),
+        secret,
+        escape(issuer),
+        DIGITS,
+        PERIOD
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "12345678901234567890", the SHA1 key of RFC 6238 appendix B
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc6238_sha1_vectors() {
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (time, expected) in vectors {
+            assert_eq!(code(RFC_KEY, (time / PERIOD) as u64, 8), expected, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn verify_accepts_the_window_around_now() {
+        let secret = base32::encode(ALPHABET, RFC_KEY);
+        let now = 1111111111;
+        let current = now / PERIOD;
+
+        for step in current - WINDOW..=current + WINDOW {
+            let input = code(RFC_KEY, step as u64, DIGITS);
+            assert_eq!(verify_at(&secret, &input, None, now), Some(step));
+        }
+        for step in [current - WINDOW - 1, current + WINDOW + 1] {
+            let input = code(RFC_KEY, step as u64, DIGITS);
+            assert_eq!(verify_at(&secret, &input, None, now), None);
+        }
+    }
+
+    #[test]
+    fn verify_refuses_used_steps() {
+        let secret = base32::encode(ALPHABET, RFC_KEY);
+        let now = 1111111111;
+        let current = now / PERIOD;
+        let input = code(RFC_KEY, current as u64, DIGITS);
+
+        assert_eq!(verify_at(&secret, &input, Some(current - 1), now), Some(current));
+        assert_eq!(verify_at(&secret, &input, Some(current), now), None);
+        assert_eq!(verify_at(&secret, &input, Some(current + 1), now), None);
+    }
+
+    #[test]
+    fn verify_refuses_malformed_input() {
+        let secret = base32::encode(ALPHABET, RFC_KEY);
+        let now = 1111111111;
+        let input = code(RFC_KEY, (now / PERIOD) as u64, DIGITS);
+
+        assert_eq!(verify_at(&secret, &format!(" {} ", input), None, now), Some(now / PERIOD));
+        assert_eq!(verify_at(&secret, &input[1..], None, now), None);
+        assert_eq!(verify_at("not base32!", &input, None, now), None);
+    }
+
+    #[test]
+    fn provisioning_uri_escapes_issuer_and_account() {
+        assert_eq!(
+            provisioning_uri("Dump & Co", "ann@example.org", "GEZDGNBV"),
+            "otpauth://totp/Dump%20%26%20Co:ann%40example.org?secret=GEZDGNBV&issuer=Dump%20%26%20Co\
+             &algorithm=SHA1&digits=6&period=30"
+        );
+        assert_eq!(
+            provisioning_uri("a:b", "ü?", "X"),
+            "otpauth://totp/a%3Ab:%C3%BC%3F?secret=X&issuer=a%3Ab&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}